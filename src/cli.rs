@@ -1,28 +1,431 @@
-use clap::{Parser, Subcommand};
-use serde_json;
-use std::io::Write;
+use crate::config_file::{self, MonitorOverride};
+use crate::mapping::{RangeMap, StringMap};
+use crate::output::{Delimiter, FieldMap, OutputConfig, OutputFormat, Record, Tone};
+use crate::preset::{self, Preset};
+use crate::selector;
+use crate::transform::TransformConfig;
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+use zbus::names::{OwnedInterfaceName, OwnedMemberName};
 use zbus::zvariant;
 
+/// Commands that replace monitoring, dispatched in `main` before the options are parsed
+const OTHER_COMMANDS: &str = "\
+Other commands, only recognized as the first argument:
+  selftest    Check the binary against a private bus and print PASS or FAIL
+  properties  List the property values of an object, to help write --status
+              (see 'waybar-dbus-monitor properties --help')";
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = OTHER_COMMANDS)]
 pub struct Config {
     /// D-Bus interface and service name to monitor
-    #[arg(long)]
-    pub interface: String,
+    #[arg(
+        long,
+        env = "WDM_INTERFACE",
+        required_unless_present_any = ["systemd_unit", "preset", "name_owner"]
+    )]
+    pub interface: Option<String>,
+
+    /// Other name of --interface, e.g. of another interface version, tried in order if the
+    /// service doesn't provide --interface; can be repeated (optional)
+    #[arg(
+        long,
+        env = "WDM_INTERFACE_FALLBACK",
+        value_name = "INTERFACE",
+        requires = "interface"
+    )]
+    pub interface_fallback: Vec<String>,
+
+    /// D-Bus member (signal/method) to monitor, can be repeated
+    #[arg(
+        long,
+        env = "WDM_MONITOR",
+        required_unless_present_any = ["systemd_unit", "preset", "name_owner", "config"]
+    )]
+    pub monitor: Vec<String>,
+
+    /// TOML file defining monitored members with their own type handlers (optional)
+    #[arg(long, env = "WDM_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Record every received message to this file for later replay (optional)
+    #[arg(long, env = "WDM_RECORD", value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Reload the config file when it changes, without restarting
+    #[arg(
+        long,
+        env = "WDM_WATCH_FILE",
+        requires = "config",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub watch_file: bool,
+
+    /// Members and handlers loaded from the config file
+    #[arg(skip)]
+    pub monitor_overrides: Vec<MonitorOverride>,
+
+    /// Only monitor signals emitted from this object path (optional)
+    #[arg(long, env = "WDM_PATH")]
+    pub path: Option<String>,
+
+    /// Treat the monitor as a property of the interface and watch PropertiesChanged for it
+    #[arg(long, env = "WDM_PROPERTIES_CHANGED", value_parser = BoolishValueParser::new())]
+    pub properties_changed: bool,
+
+    /// With --properties-changed, fetch monitored properties that are invalidated without a value
+    #[arg(long, env = "WDM_FETCH_INVALIDATED", value_parser = BoolishValueParser::new())]
+    pub fetch_invalidated: bool,
+
+    /// Type of message to monitor; method calls are observed by becoming a bus monitor
+    #[arg(
+        long,
+        env = "WDM_MSG_TYPE",
+        value_enum,
+        default_value_t = MessageType::Signal,
+        conflicts_with_all = ["properties_changed", "status_defer"]
+    )]
+    pub msg_type: MessageType,
+
+    /// Select the value stored under this key of a dictionary argument (optional)
+    #[arg(long, env = "WDM_DICT_KEY", value_name = "KEY")]
+    pub dict_key: Option<String>,
 
-    /// D-Bus member (signal/method) to monitor
-    #[arg(long)]
-    pub monitor: String,
+    /// Descend further into nested dictionaries, one key per use (optional)
+    #[arg(
+        long,
+        env = "WDM_NESTED_KEY",
+        value_name = "KEY",
+        requires = "dict_key"
+    )]
+    pub nested_key: Vec<String>,
+
+    /// Select the struct whose first field is this value from an array of structs (optional)
+    #[arg(long, env = "WDM_ARRAY_STRUCT_KEY", value_name = "VALUE")]
+    pub array_struct_key: Option<String>,
+
+    /// Field of the struct selected by --array-struct-key to monitor, counting from 0
+    #[arg(
+        long,
+        env = "WDM_ARRAY_STRUCT_FIELD",
+        value_name = "N",
+        default_value_t = 1,
+        requires = "array_struct_key"
+    )]
+    pub array_struct_field: usize,
+
+    /// Select this field of a struct, counting from 0; repeat to descend into nested structs.
+    /// On its own, the first index selects the message argument, or the field of a lone struct
+    /// argument (optional)
+    #[arg(long, env = "WDM_STRUCT_FIELD", value_name = "N")]
+    pub struct_field: Vec<usize>,
+
+    /// Message bus to connect to
+    #[arg(long, env = "WDM_BUS", value_enum, default_value_t = BusType::Auto)]
+    pub bus: BusType,
+
+    /// Monitor whether a systemd unit is active (replaces --interface, --monitor and --status)
+    #[arg(
+        long,
+        env = "WDM_SYSTEMD_UNIT",
+        value_name = "UNIT",
+        conflicts_with_all = ["interface", "monitor", "status", "path", "properties_changed"]
+    )]
+    pub systemd_unit: Option<String>,
+
+    /// Monitor a well-known signal (replaces --interface, --monitor, --status and --bus)
+    #[arg(
+        long,
+        env = "WDM_PRESET",
+        value_enum,
+        conflicts_with_all = ["systemd_unit", "interface", "monitor", "status", "path", "properties_changed"]
+    )]
+    pub preset: Option<Preset>,
+
+    /// Monitor whether a well-known bus name has an owner, i.e. the service is running
+    /// (replaces --interface, --monitor and --status)
+    #[arg(
+        long,
+        env = "WDM_NAME_OWNER",
+        value_name = "NAME",
+        conflicts_with_all = [
+            "systemd_unit", "preset", "interface", "monitor", "config", "status", "path",
+            "properties_changed", "msg_type", "watch_service"
+        ]
+    )]
+    pub name_owner: Option<String>,
 
     /// Initial status check in format "service/path interface property" (optional)
-    #[arg(long)]
+    #[arg(long, env = "WDM_STATUS")]
     pub status: Option<String>,
 
+    /// Numeric property for the JSON percentage field, in format "service/path interface property" (optional)
+    #[arg(long, env = "WDM_PERCENTAGE_STATUS")]
+    pub percentage_status: Option<String>,
+
+    /// Also refresh the percentage every this many seconds (optional)
+    #[arg(
+        long,
+        env = "WDM_PERCENTAGE_INTERVAL",
+        value_name = "SECONDS",
+        requires = "percentage_status"
+    )]
+    pub percentage_interval: Option<u64>,
+
+    /// With --properties-changed, also poll the --status property every this many seconds
+    /// and emit it if it changed without a signal (optional)
+    #[arg(long, env = "WDM_POLL_BACKUP", value_name = "SECONDS")]
+    pub poll_backup: Option<u64>,
+
+    /// Only emit a value once it was seen this many times in a row (optional)
+    #[arg(long, env = "WDM_STABLE_COUNT", value_name = "K")]
+    pub stable_count: Option<usize>,
+
+    /// Emit how many of the monitored boolean members are currently true, instead of their values
+    #[arg(long, env = "WDM_COUNT_TRUE", value_parser = BoolishValueParser::new())]
+    pub count_true: bool,
+
+    /// Show how long ago the value last changed, refreshed every this many seconds (optional)
+    #[arg(long, env = "WDM_SHOW_AGE", value_name = "SECONDS")]
+    pub show_age: Option<u64>,
+
+    /// Text format for --show-age, with {text} for the value and {age} for the age
+    #[arg(
+        long,
+        env = "WDM_AGE_FORMAT",
+        value_name = "FORMAT",
+        default_value = "{text} ({age} ago)",
+        requires = "show_age"
+    )]
+    pub age_format: String,
+
+    /// Mark the value as stale with a CSS class when no value arrived for this many seconds (optional)
+    #[arg(long, env = "WDM_STALE_AFTER", value_name = "SECONDS")]
+    pub stale_after: Option<u64>,
+
+    /// CSS class for values older than --stale-after
+    #[arg(
+        long,
+        env = "WDM_STALE_CLASS",
+        value_name = "CLASS",
+        default_value = "stale",
+        requires = "stale_after"
+    )]
+    pub stale_class: String,
+
+    /// Watch a well-known bus name and react immediately when its owner vanishes or appears;
+    /// without a value, the service of --status is watched (optional)
+    #[arg(
+        long,
+        env = "WDM_WATCH_SERVICE",
+        value_name = "NAME",
+        conflicts_with = "msg_type"
+    )]
+    pub watch_service: Option<Option<String>>,
+
+    /// Output to emit when the watched service vanishes, instead of E503 (optional)
+    #[arg(
+        long,
+        env = "WDM_SERVICE_GONE_OUTPUT",
+        value_name = "STRING",
+        requires = "watch_service"
+    )]
+    pub service_gone_output: Option<String>,
+
+    /// Query --status before subscribing to the monitored messages, as in earlier versions
+    #[arg(
+        long,
+        env = "WDM_STATUS_BEFORE_MATCH",
+        conflicts_with = "status_defer",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub status_before_match: bool,
+
+    /// Only run the status query if no signal arrives within this many milliseconds (optional)
+    #[arg(long, env = "WDM_STATUS_DEFER", value_name = "MS", requires = "status")]
+    pub status_defer: Option<u64>,
+
+    /// Print the resolved configuration, including presets, config file and defaults, then exit
+    #[arg(long, env = "WDM_SHOW_CONFIG", value_parser = BoolishValueParser::new())]
+    pub show_config: bool,
+
+    /// Listen for the given number of seconds, report whether the monitored messages arrive, then exit
+    #[arg(long, env = "WDM_WARMUP_CHECK", value_name = "SECONDS")]
+    pub warmup_check: Option<u64>,
+
+    /// Reconnect with backoff when the connection is lost instead of exiting
+    #[arg(long, env = "WDM_RECONNECT", value_parser = BoolishValueParser::new())]
+    pub reconnect: bool,
+
+    /// Like --reconnect, but also restart after fatal errors, so the process never exits
+    /// (a misconfiguration then keeps emitting E404)
+    #[arg(long, env = "WDM_NEVER_EXIT", value_parser = BoolishValueParser::new())]
+    pub never_exit: bool,
+
+    /// Give up after this many reconnections in a row without a stable connection (optional)
+    #[arg(long, env = "WDM_MAX_RECONNECTS", value_name = "N")]
+    pub max_reconnects: Option<u32>,
+
+    /// Output to emit when --max-reconnects is reached, instead of the error code (optional)
+    #[arg(
+        long,
+        env = "WDM_RECONNECT_EXHAUSTED_OUTPUT",
+        value_name = "STRING",
+        requires = "max_reconnects"
+    )]
+    pub reconnect_exhausted_output: Option<String>,
+
+    /// Keep running idle when --max-reconnects is reached instead of exiting
+    #[arg(
+        long,
+        env = "WDM_HOLD_WHEN_EXHAUSTED",
+        requires = "max_reconnects",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub hold_when_exhausted: bool,
+
+    /// Also emit every processed value as a string signal "interface member" on path "/" (optional)
+    #[arg(long, env = "WDM_EMIT_SIGNAL", value_name = "SIGNAL")]
+    pub emit_signal: Option<String>,
+
+    /// Emit --cleared-output after the duration in milliseconds carried by the monitored
+    /// message's argument at this index, counting from 0 (optional)
+    #[arg(
+        long,
+        env = "WDM_CLEAR_AFTER_FROM_ARG",
+        value_name = "INDEX",
+        conflicts_with_all = ["properties_changed", "name_owner"]
+    )]
+    pub clear_after_from_arg: Option<usize>,
+
+    /// Output to emit when the --clear-after-from-arg duration elapses
+    #[arg(
+        long,
+        env = "WDM_CLEARED_OUTPUT",
+        value_name = "STRING",
+        default_value = "",
+        requires = "clear_after_from_arg"
+    )]
+    pub cleared_output: String,
+
+    /// Emit empty text with this CSS class until the first value is known (optional)
+    #[arg(long, env = "WDM_INITIALIZING_CLASS", value_name = "CLASS")]
+    pub initializing_class: Option<String>,
+
+    /// Output to emit whenever the D-Bus connection is established (optional)
+    #[arg(long, env = "WDM_ON_CONNECT_OUTPUT", value_name = "STRING")]
+    pub on_connect_output: Option<String>,
+
+    /// How to handle messages whose values the type handler can't process
+    #[arg(long, env = "WDM_ON_UNEXPECTED", value_enum, default_value_t = UnexpectedPolicy::Error)]
+    pub on_unexpected: UnexpectedPolicy,
+
+    /// Minimum interval in milliseconds between two emitted error codes (optional)
+    #[arg(long, env = "WDM_ERROR_THROTTLE", value_name = "MS")]
+    pub error_throttle: Option<u64>,
+
+    /// Log identical errors about received messages at most once per this many milliseconds (optional)
+    #[arg(long, env = "WDM_LOG_THROTTLE", value_name = "MS")]
+    pub log_throttle: Option<u64>,
+
+    /// Output framing: JSON lines for waybar, or length-prefixed records for other consumers
+    #[arg(long, env = "WDM_FORMAT", value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
+
+    /// Separator after each JSON record: newline, nul or any custom string
+    #[arg(
+        long,
+        env = "WDM_DELIMITER",
+        value_name = "DELIMITER",
+        default_value = "newline",
+        value_parser = Delimiter::parse
+    )]
+    pub delimiter: Delimiter,
+
+    /// Rename emitted JSON fields, e.g. "text=label,class=state" (optional)
+    #[arg(long, env = "WDM_JSON_FIELD_MAP", value_name = "MAP", value_parser = FieldMap::parse)]
+    pub json_field_map: Option<FieldMap>,
+
+    /// Number of records buffered while stdout is not being read; when full, only the latest is kept
+    #[arg(long, env = "WDM_OUTPUT_BUFFER", value_name = "N", default_value_t = 8)]
+    pub output_buffer: usize,
+
+    /// Shell command that receives each value's text on stdin and replaces it with its stdout (optional)
+    #[arg(long, env = "WDM_TRANSFORM_COMMAND", value_name = "CMD")]
+    pub transform_command: Option<String>,
+
+    /// Time in milliseconds after which a hung transform command is killed
+    #[arg(
+        long,
+        env = "WDM_TRANSFORM_TIMEOUT",
+        value_name = "MS",
+        default_value_t = 1000,
+        requires = "transform_command"
+    )]
+    pub transform_timeout: u64,
+
+    /// Truncate output text to this many characters, ending with an ellipsis (optional)
+    #[arg(long, env = "WDM_MAX_LENGTH", value_name = "N")]
+    pub max_length: Option<usize>,
+
+    /// Don't emit a record identical to the previous one, whether a value or an error code
+    #[arg(long, env = "WDM_DEDUP", value_parser = BoolishValueParser::new())]
+    pub dedup: bool,
+
+    /// Color output by state when stdout is a terminal, for interactive debugging
+    #[arg(long, env = "WDM_COLOR", value_parser = BoolishValueParser::new())]
+    pub color: bool,
+
     /// Type handler for the monitored data
     #[command(subcommand)]
     pub type_handler: TypeHandler,
 }
 
+/// Message bus selection
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusType {
+    /// Try the session bus first, fall back to the system bus
+    Auto,
+    /// Session bus only
+    Session,
+    /// System bus only
+    System,
+}
+
+/// Type of the monitored D-Bus messages
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// Signals emitted by services
+    Signal,
+    /// Method calls made by clients
+    MethodCall,
+}
+
+impl From<MessageType> for zbus::message::Type {
+    fn from(msg_type: MessageType) -> Self {
+        match msg_type {
+            MessageType::Signal => zbus::message::Type::Signal,
+            MessageType::MethodCall => zbus::message::Type::MethodCall,
+        }
+    }
+}
+
+/// Handling of messages with unexpected values
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnexpectedPolicy {
+    /// Ignore the message
+    Skip,
+    /// Emit E422
+    Error,
+    /// Emit the Debug form of the message's first argument
+    Raw,
+}
+
 #[derive(Debug, Clone)]
 pub struct StatusConfig {
     pub service: String,
@@ -31,57 +434,437 @@ pub struct StatusConfig {
     pub property: String,
 }
 
-impl Config {
-    /// Parse and validate the status configuration
-    pub fn parse_status(&self) -> Result<Option<StatusConfig>, String> {
-        if let Some(status_str) = &self.status {
-            // Split by whitespace into exactly 3 parts
-            let parts: Vec<&str> = status_str.trim().split_whitespace().collect();
+impl StatusConfig {
+    /// Property reference for --show-config
+    fn settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "service": self.service,
+            "path": self.object_path,
+            "interface": self.interface,
+            "property": self.property,
+        })
+    }
+
+    /// Parse a property reference in format "service/path interface property"
+    ///
+    /// The service and the path may also be separated by whitespace, as in
+    /// "service /path interface property".
+    pub fn parse(status_str: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = status_str.split_whitespace().collect();
 
-            if parts.len() != 3 {
+        let (service, object_path, interface, property) = match parts.as_slice() {
+            [service_path, interface, property] => {
+                let (service, object_path) = parse_service_path(service_path)?;
+                (service, object_path, *interface, *property)
+            }
+            [service, object_path, interface, property] if object_path.starts_with('/') => {
+                validate_service_path(service, object_path)?;
+                (
+                    service.to_string(),
+                    object_path.to_string(),
+                    *interface,
+                    *property,
+                )
+            }
+            _ => {
                 return Err(format!(
-                    "Invalid status format. Expected: 'service/path interface property', got: '{}'",
+                    "Invalid status format. Expected: 'service/path interface property' \
+                     or 'service /path interface property', got: '{}'",
                     status_str
                 ));
             }
+        };
 
-            // First part must contain exactly one slash to separate service and path
-            let service_path = parts[0];
-            if !service_path.contains('/') {
-                return Err(format!(
-                    "Invalid format: '{}'. First parameter must be 'service/path'",
-                    service_path
-                ));
-            }
+        zbus::names::InterfaceName::try_from(interface)
+            .map_err(|e| format!("Invalid interface '{}': {}", interface, e))?;
 
-            // Split service and path at the slash
-            let slash_pos = service_path.find('/').unwrap();
-            let service = service_path[..slash_pos].to_string();
-            let object_path = service_path[slash_pos..].to_string();
+        Ok(StatusConfig {
+            service,
+            object_path,
+            interface: interface.to_string(),
+            property: property.to_string(),
+        })
+    }
+}
 
-            // Basic validation
-            if service.is_empty() {
-                return Err("Service name cannot be empty".to_string());
-            }
-            if object_path.len() <= 1 && object_path != "/" {
-                return Err("Object path must be '/' or longer".to_string());
+/// Split an object reference in format "service/path" into the service and the object path
+///
+/// Bus names can't contain a slash, so the first slash always starts the path,
+/// however many dots the name contains.
+pub fn parse_service_path(service_path: &str) -> Result<(String, String), String> {
+    let Some(slash_pos) = service_path.find('/') else {
+        return Err(format!(
+            "Invalid format: '{}'. First parameter must be 'service/path'",
+            service_path
+        ));
+    };
+    let (service, object_path) = service_path.split_at(slash_pos);
+
+    validate_service_path(service, object_path)?;
+    Ok((service.to_string(), object_path.to_string()))
+}
+
+/// Check that a service and an object path are valid D-Bus names
+fn validate_service_path(service: &str, object_path: &str) -> Result<(), String> {
+    if service.is_empty() {
+        return Err("Service name cannot be empty".to_string());
+    }
+    zbus::names::BusName::try_from(service)
+        .map_err(|e| format!("Invalid service name '{}': {}", service, e))?;
+    zvariant::ObjectPath::try_from(object_path)
+        .map_err(|e| format!("Invalid object path '{}': {}", object_path, e))?;
+    Ok(())
+}
+
+/// Signal emitted for every processed value with --emit-signal
+#[derive(Debug, Clone)]
+pub struct EmitSignalConfig {
+    pub interface: OwnedInterfaceName,
+    pub member: OwnedMemberName,
+}
+
+impl EmitSignalConfig {
+    /// Parse a signal in format "interface member"
+    pub fn parse(signal_str: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = signal_str.split_whitespace().collect();
+
+        let [interface, member] = parts.as_slice() else {
+            return Err(format!(
+                "Invalid signal format. Expected: 'interface member', got: '{}'",
+                signal_str
+            ));
+        };
+
+        Ok(EmitSignalConfig {
+            interface: OwnedInterfaceName::try_from(*interface)
+                .map_err(|e| format!("Invalid interface '{}': {}", interface, e))?,
+            member: OwnedMemberName::try_from(*member)
+                .map_err(|e| format!("Invalid member '{}': {}", member, e))?,
+        })
+    }
+}
+
+/// Name of an option value as given on the command line
+fn value_name(value: impl ValueEnum) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+impl Config {
+    /// D-Bus interface to monitor
+    pub fn interface(&self) -> &str {
+        self.interface.as_deref().unwrap_or_default()
+    }
+
+    /// Interfaces to negotiate: --interface first, then the --interface-fallback names
+    pub fn interface_candidates(&self) -> Vec<String> {
+        self.interface
+            .iter()
+            .chain(self.interface_fallback.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// D-Bus members to monitor: those given on the command line, then those from the config file
+    pub fn monitors(&self) -> Vec<&str> {
+        let mut monitors: Vec<&str> = self.monitor.iter().map(String::as_str).collect();
+        for monitor_override in &self.monitor_overrides {
+            if !monitors.contains(&monitor_override.member.as_str()) {
+                monitors.push(&monitor_override.member);
             }
+        }
+        monitors
+    }
 
-            Ok(Some(StatusConfig {
-                service,
-                object_path,
-                interface: parts[1].to_string(),
-                property: parts[2].to_string(),
-            }))
-        } else {
-            Ok(None)
+    /// Primary D-Bus member, used for the initial status query
+    pub fn monitor(&self) -> &str {
+        self.monitors().first().copied().unwrap_or_default()
+    }
+
+    /// Type handler for a member
+    ///
+    /// The first config file entry for the member takes precedence over the
+    /// command line handler, which is used for all other members.
+    pub fn handler_for(&self, member: &str) -> &TypeHandler {
+        self.monitor_overrides
+            .iter()
+            .find(|monitor_override| monitor_override.member == member)
+            .map_or(&self.type_handler, |monitor_override| {
+                &monitor_override.handler
+            })
+    }
+
+    /// Dictionary keys leading to the monitored value
+    pub fn key_path(&self) -> Vec<String> {
+        self.dict_key
+            .iter()
+            .chain(self.nested_key.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the monitored value is selected from within the message argument
+    pub fn has_selector(&self) -> bool {
+        self.dict_key.is_some() || self.array_struct_key.is_some() || !self.struct_field.is_empty()
+    }
+
+    /// Expand presets and load the config file into the plain monitoring options
+    pub fn resolve(&mut self) -> Result<(), String> {
+        if let Some(unit) = self.systemd_unit.clone() {
+            preset::apply_systemd_unit(self, &unit);
+        }
+        if let Some(preset) = self.preset {
+            preset::apply_preset(self, preset)?;
+        }
+        if self.name_owner.is_some() {
+            preset::apply_name_owner(self);
+        }
+
+        if let Some(path) = &self.config {
+            self.monitor_overrides = config_file::load(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Load the config file again, keeping the current members and handlers on failure
+    ///
+    /// Returns whether the set of monitored members changed, which requires a
+    /// new subscription. Handler changes take effect with the next message.
+    pub fn reload_config_file(&mut self) -> Result<bool, String> {
+        let Some(path) = &self.config else {
+            return Ok(false);
+        };
+        let previous_monitors: Vec<String> =
+            self.monitors().into_iter().map(str::to_string).collect();
+
+        let overrides = config_file::load(path)?;
+        let previous_overrides = std::mem::replace(&mut self.monitor_overrides, overrides);
+        if let Err(e) = self.validate() {
+            self.monitor_overrides = previous_overrides;
+            return Err(e);
+        }
+
+        Ok(self.monitors() != previous_monitors)
+    }
+
+    /// Parse and validate the status configuration
+    pub fn parse_status(&self) -> Result<Option<StatusConfig>, String> {
+        self.status.as_deref().map(StatusConfig::parse).transpose()
+    }
+
+    /// Parse and validate the --emit-signal target
+    pub fn parse_emit_signal(&self) -> Result<Option<EmitSignalConfig>, String> {
+        self.emit_signal
+            .as_deref()
+            .map(EmitSignalConfig::parse)
+            .transpose()
+    }
+
+    /// Well-known bus name to watch for owner changes
+    ///
+    /// An explicit --watch-service name is used as-is, a bare --watch-service
+    /// watches the service of the --status query.
+    pub fn watched_service(&self) -> Result<Option<String>, String> {
+        match &self.watch_service {
+            None => Ok(None),
+            Some(Some(service)) => Ok(Some(service.clone())),
+            Some(None) => match self.parse_status()? {
+                Some(status_config) => Ok(Some(status_config.service)),
+                None => Err("--watch-service without a name requires --status".to_string()),
+            },
         }
     }
 
+    /// Parse and validate the percentage status configuration
+    pub fn parse_percentage_status(&self) -> Result<Option<StatusConfig>, String> {
+        self.percentage_status
+            .as_deref()
+            .map(StatusConfig::parse)
+            .transpose()
+    }
+
+    /// Build the output configuration from the command line options
+    pub fn output_config(&self) -> OutputConfig {
+        OutputConfig {
+            error_throttle: self.error_throttle.map(Duration::from_millis),
+            format: self.format,
+            delimiter: self.delimiter.clone(),
+            field_map: self.json_field_map.clone().unwrap_or_default(),
+            buffer: self.output_buffer,
+            transform: self
+                .transform_command
+                .as_ref()
+                .map(|command| TransformConfig {
+                    command: command.clone(),
+                    timeout: Duration::from_millis(self.transform_timeout),
+                }),
+            max_length: self.max_length,
+            // Never send escape codes to waybar or other non-terminal consumers
+            color: self.color && std::io::stdout().is_terminal(),
+            dedup: self.dedup,
+        }
+    }
+
+    /// Effective settings for --show-config, grouped by what they affect
+    ///
+    /// Options that only apply together with another one are left out while
+    /// that one is unset, so the defaults shown are the ones in effect.
+    pub fn settings(&self) -> serde_json::Value {
+        let status = |status: Result<Option<StatusConfig>, String>| {
+            status.ok().flatten().map(|status| status.settings())
+        };
+        let monitors: Vec<serde_json::Value> = self
+            .monitors()
+            .into_iter()
+            .map(|member| {
+                serde_json::json!({
+                    "member": member,
+                    "handler": self.handler_for(member).settings(),
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "bus": value_name(self.bus),
+            "interface": self.interface,
+            "interface_fallback": self.interface_fallback,
+            "path": self.path,
+            "msg_type": value_name(self.msg_type),
+            "properties_changed": self.properties_changed,
+            "fetch_invalidated": self.fetch_invalidated,
+            "monitors": monitors,
+            "config": self.config.as_ref().map(|path| path.display().to_string()),
+            "watch_file": self.watch_file,
+            "record": self.record.as_ref().map(|path| path.display().to_string()),
+            "selector": {
+                "dict_key": self.key_path(),
+                "array_struct_key": self.array_struct_key,
+                "array_struct_field": self.array_struct_key.as_ref().map(|_| self.array_struct_field),
+                "struct_field": self.struct_field,
+            },
+            "status": {
+                "query": status(self.parse_status()),
+                "before_match": self.status_before_match,
+                "defer_ms": self.status_defer,
+                "poll_backup_s": self.poll_backup,
+                "percentage": status(self.parse_percentage_status()),
+                "percentage_interval_s": self.percentage_interval,
+                "watch_service": self.watched_service().ok().flatten(),
+                "service_gone_output": self.service_gone_output,
+            },
+            "values": {
+                "stable_count": self.stable_count,
+                "count_true": self.count_true,
+                "show_age_s": self.show_age,
+                "age_format": self.show_age.map(|_| &self.age_format),
+                "stale_after_s": self.stale_after,
+                "stale_class": self.stale_after.map(|_| &self.stale_class),
+                "clear_after_from_arg": self.clear_after_from_arg,
+                "cleared_output": self.clear_after_from_arg.map(|_| &self.cleared_output),
+                "initializing_class": self.initializing_class,
+                "on_connect_output": self.on_connect_output,
+                "on_unexpected": value_name(self.on_unexpected),
+                "emit_signal": self.emit_signal,
+            },
+            "reconnect": {
+                "reconnect": self.reconnect,
+                "never_exit": self.never_exit,
+                "max_reconnects": self.max_reconnects,
+                "exhausted_output": self.reconnect_exhausted_output,
+                "hold_when_exhausted": self.hold_when_exhausted,
+            },
+            "output": {
+                "format": value_name(self.format),
+                "delimiter": self.delimiter.name(),
+                "json_field_map": self.json_field_map.as_ref().map(FieldMap::spec),
+                "buffer": self.output_buffer,
+                "transform_command": self.transform_command,
+                "transform_timeout_ms": self.transform_command.as_ref().map(|_| self.transform_timeout),
+                "max_length": self.max_length,
+                "dedup": self.dedup,
+                "color": self.color,
+                "error_throttle_ms": self.error_throttle,
+                "log_throttle_ms": self.log_throttle,
+            },
+        })
+    }
+
     /// Validate the configuration and return an error if invalid
     pub fn validate(&self) -> Result<(), String> {
-        // Validate status format if provided
-        self.parse_status().map(|_| ())
+        // Validate status formats if provided
+        self.parse_status()?;
+        self.parse_percentage_status()?;
+        self.watched_service()?;
+        self.parse_emit_signal()?;
+
+        if self.output_buffer == 0 {
+            return Err("--output-buffer must be at least 1".to_string());
+        }
+
+        // Binary records are length-prefixed and have no separator
+        if self.format == OutputFormat::Binary && self.delimiter != Delimiter::Newline {
+            return Err("--delimiter cannot be combined with --format binary".to_string());
+        }
+
+        if self.monitors().is_empty() {
+            return Err("No member to monitor, use --monitor or the config file".to_string());
+        }
+        // Checked here rather than by clap, since --systemd-unit provides both
+        if self.poll_backup.is_some() && !(self.properties_changed && self.status.is_some()) {
+            return Err("--poll-backup requires --properties-changed and --status".to_string());
+        }
+        if self.stable_count == Some(0) {
+            return Err("--stable-count must be at least 1".to_string());
+        }
+        if self.show_age == Some(0) {
+            return Err("--show-age must be at least 1 second".to_string());
+        }
+        if self.stale_after == Some(0) {
+            return Err("--stale-after must be at least 1 second".to_string());
+        }
+        if self.fetch_invalidated && !self.properties_changed {
+            return Err("--fetch-invalidated requires --properties-changed".to_string());
+        }
+        if self.status_before_match && self.status.is_none() {
+            return Err("--status-before-match requires --status".to_string());
+        }
+        // Checked here rather than by clap, since either option enables reconnecting
+        if self.max_reconnects.is_some() && !(self.reconnect || self.never_exit) {
+            return Err("--max-reconnects requires --reconnect or --never-exit".to_string());
+        }
+        if self.max_reconnects == Some(0) {
+            return Err("--max-reconnects must be at least 1".to_string());
+        }
+        if self.percentage_status.is_some()
+            && matches!(
+                self.type_handler,
+                TypeHandler::Integer {
+                    percentage_only: true,
+                    ..
+                }
+            )
+        {
+            return Err(
+                "--percentage-only cannot be combined with --percentage-status".to_string(),
+            );
+        }
+        if self.name_owner.is_some() && !matches!(self.type_handler, TypeHandler::Boolean { .. }) {
+            return Err("--name-owner requires the boolean type handler".to_string());
+        }
+        if self.count_true
+            && self
+                .monitors()
+                .into_iter()
+                .any(|member| !matches!(self.handler_for(member), TypeHandler::Boolean { .. }))
+        {
+            return Err(
+                "--count-true requires the boolean type handler for every member".to_string(),
+            );
+        }
+
+        self.type_handler.validate()
     }
 }
 
@@ -90,24 +873,274 @@ pub enum TypeHandler {
     /// Monitor a boolean value
     Boolean {
         /// String to return when value is true
-        #[arg(long, default_value = "true")]
+        #[arg(long, env = "WDM_RETURN_TRUE", default_value = "true")]
         return_true: String,
 
         /// String to return when value is false
-        #[arg(long, default_value = "false")]
+        #[arg(long, env = "WDM_RETURN_FALSE", default_value = "false")]
         return_false: String,
+
+        /// Treat string values as true when equal to this, false otherwise (optional)
+        #[arg(long, env = "WDM_TRUE_VALUE")]
+        true_value: Option<String>,
+    },
+    /// Monitor a string value
+    String {
+        /// String to return when the value is empty (optional)
+        #[arg(long, env = "WDM_EMPTY_STRING_OUTPUT")]
+        empty_string_output: Option<String>,
+
+        /// Collapse runs of whitespace into single spaces and trim both ends
+        #[arg(long, env = "WDM_NORMALIZE_WHITESPACE", value_parser = BoolishValueParser::new())]
+        normalize_whitespace: bool,
+
+        /// File mapping strings onto labels, one "STRING=LABEL" per line (optional)
+        #[arg(long, env = "WDM_MAP_FILE", value_name = "PATH", value_parser = StringMap::load)]
+        map_file: Option<StringMap>,
+    },
+    /// Monitor an integer value
+    Integer {
+        /// Emit the difference to the previous value instead of the value itself
+        #[arg(long, env = "WDM_EMIT_DELTA", value_parser = BoolishValueParser::new())]
+        emit_delta: bool,
+
+        /// With --emit-delta, emit the first value as-is instead of suppressing it
+        #[arg(
+            long,
+            env = "WDM_DELTA_FIRST_ABSOLUTE",
+            requires = "emit_delta",
+            value_parser = BoolishValueParser::new()
+        )]
+        delta_first_absolute: bool,
+
+        /// Glyphs to map the value onto, from lowest to highest (e.g. "▁▂▃▄▅▆▇") (optional)
+        #[arg(long, env = "WDM_BARS", value_name = "CHARS")]
+        bars: Option<String>,
+
+        /// Value mapped to the first glyph of --bars
+        #[arg(
+            long,
+            env = "WDM_BARS_MIN",
+            default_value_t = 0,
+            allow_negative_numbers = true,
+            requires = "bars"
+        )]
+        bars_min: i64,
+
+        /// Value mapped to the last glyph of --bars
+        #[arg(
+            long,
+            env = "WDM_BARS_MAX",
+            default_value_t = 100,
+            allow_negative_numbers = true,
+            requires = "bars"
+        )]
+        bars_max: i64,
+
+        /// Map value ranges onto labels, e.g. "..30=low,31..70=mid,71..=high" (optional)
+        #[arg(
+            long,
+            env = "WDM_MAP",
+            value_name = "RANGES",
+            value_parser = RangeMap::parse,
+            conflicts_with = "bars"
+        )]
+        map: Option<RangeMap>,
+
+        /// File mapping value ranges onto labels, one --map entry per line (optional)
+        #[arg(
+            long,
+            env = "WDM_MAP_FILE",
+            value_name = "PATH",
+            value_parser = RangeMap::load,
+            conflicts_with_all = ["bars", "map"]
+        )]
+        map_file: Option<RangeMap>,
+
+        /// Add the raw value in parentheses to the mapped label or --bars glyph (optional)
+        #[arg(
+            long,
+            env = "WDM_WITH_RAW",
+            value_enum,
+            value_name = "POSITION",
+            num_args = 0..=1,
+            default_missing_value = "suffix"
+        )]
+        with_raw: Option<RawPosition>,
+
+        /// Group thousands with a separator, "," unless another character is given (optional)
+        #[arg(
+            long,
+            env = "WDM_GROUP_DIGITS",
+            value_name = "SEPARATOR",
+            num_args = 0..=1,
+            default_missing_value = ","
+        )]
+        group_digits: Option<char>,
+
+        /// Text appended to the number, e.g. " B" (optional)
+        #[arg(long, env = "WDM_UNIT", value_name = "STRING")]
+        unit: Option<String>,
+
+        /// Emit empty text and the value as the percentage field, for progress bars styled via CSS
+        #[arg(
+            long,
+            env = "WDM_PERCENTAGE_ONLY",
+            conflicts_with_all = ["bars", "map", "map_file", "with_raw", "group_digits", "unit"],
+            value_parser = BoolishValueParser::new()
+        )]
+        percentage_only: bool,
+    },
+    /// Monitor a floating point value
+    Float {
+        /// Number of decimal places to show; only affects the text, not comparisons (optional)
+        #[arg(long, env = "WDM_PRECISION", value_name = "DIGITS")]
+        precision: Option<usize>,
+
+        /// Treat values differing by at most this much from the shown value as unchanged
+        #[arg(
+            long,
+            env = "WDM_FLOAT_EPSILON",
+            value_name = "E",
+            default_value_t = DEFAULT_FLOAT_EPSILON
+        )]
+        float_epsilon: f64,
     },
-    // TODO: Implement additional type handlers:
-    // String { ... },
-    // Integer { ... },
+}
+
+/// Default --float-epsilon, absorbing rounding errors but no actual changes
+const DEFAULT_FLOAT_EPSILON: f64 = 1e-9;
+
+/// Where --with-raw places the raw value relative to the mapped label
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RawPosition {
+    /// "mid (45)"
+    Suffix,
+    /// "(45) mid"
+    Prefix,
+}
+
+/// Mutable state kept by the type handlers between messages
+///
+/// The state belongs to a single connection and is reset when reconnecting.
+#[derive(Debug, Default)]
+pub struct HandlerState {
+    /// Last integer value seen, for --emit-delta
+    previous_integer: Option<i64>,
+    /// Last boolean value seen, for --count-true
+    pub last_boolean: Option<bool>,
+    /// Last float value shown, the reference for --float-epsilon
+    previous_float: Option<f64>,
 }
 
 impl TypeHandler {
+    /// Effective handler options for --show-config, including their defaults
+    pub fn settings(&self) -> serde_json::Value {
+        match self {
+            TypeHandler::Boolean {
+                return_true,
+                return_false,
+                true_value,
+            } => serde_json::json!({
+                "type": "boolean",
+                "return_true": return_true,
+                "return_false": return_false,
+                "true_value": true_value,
+            }),
+            TypeHandler::String {
+                empty_string_output,
+                normalize_whitespace,
+                map_file,
+            } => serde_json::json!({
+                "type": "string",
+                "empty_string_output": empty_string_output,
+                "normalize_whitespace": normalize_whitespace,
+                "map_file": map_file.as_ref().map(StringMap::labels),
+            }),
+            TypeHandler::Integer {
+                emit_delta,
+                delta_first_absolute,
+                bars,
+                bars_min,
+                bars_max,
+                map,
+                map_file,
+                with_raw,
+                group_digits,
+                unit,
+                percentage_only,
+            } => serde_json::json!({
+                "type": "integer",
+                "emit_delta": emit_delta,
+                "delta_first_absolute": delta_first_absolute,
+                "bars": bars,
+                "bars_min": bars.as_ref().map(|_| bars_min),
+                "bars_max": bars.as_ref().map(|_| bars_max),
+                "map": map.as_ref().or(map_file.as_ref()).map(RangeMap::spec),
+                "with_raw": with_raw.map(value_name),
+                "group_digits": group_digits,
+                "unit": unit,
+                "percentage_only": percentage_only,
+            }),
+            TypeHandler::Float {
+                precision,
+                float_epsilon,
+            } => serde_json::json!({
+                "type": "float",
+                "precision": precision,
+                "float_epsilon": float_epsilon,
+            }),
+        }
+    }
+
+    /// Validate the handler options and return an error if invalid
+    pub fn validate(&self) -> Result<(), String> {
+        if let TypeHandler::Integer {
+            bars: None,
+            map: None,
+            map_file: None,
+            with_raw: Some(_),
+            ..
+        } = self
+        {
+            return Err("--with-raw requires --map, --map-file or --bars".to_string());
+        }
+        if let TypeHandler::Integer {
+            bars: Some(bars),
+            bars_min,
+            bars_max,
+            ..
+        } = self
+        {
+            if bars.is_empty() {
+                return Err("--bars needs at least one glyph".to_string());
+            }
+            if bars_min >= bars_max {
+                return Err(format!(
+                    "--bars-min ({}) must be less than --bars-max ({})",
+                    bars_min, bars_max
+                ));
+            }
+        }
+        match self {
+            TypeHandler::Float { float_epsilon, .. }
+                if !float_epsilon.is_finite() || *float_epsilon < 0.0 =>
+            {
+                Err(format!(
+                    "--float-epsilon must be a non-negative number, got {}",
+                    float_epsilon
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Extract a boolean from various zvariant::Value types
-    fn extract_boolean(value: &zvariant::Value) -> Option<bool> {
+    fn extract_boolean(value: &zvariant::Value, true_value: Option<&str>) -> Option<bool> {
         match value {
             zvariant::Value::Bool(b) => Some(*b),
-            zvariant::Value::Value(v) => Self::extract_boolean(v),
+            zvariant::Value::Str(s) if true_value.is_some() => Some(Some(s.as_str()) == true_value),
+            zvariant::Value::Value(v) => Self::extract_boolean(v, true_value),
             _ => {
                 log::debug!("warn: Could not extract boolean from value: {:?}", value);
                 None
@@ -115,69 +1148,877 @@ impl TypeHandler {
         }
     }
 
-    /// Helper method to format and print a boolean value as Waybar JSON
-    fn format_and_print_boolean(&self, value: bool) -> Result<(), String> {
-        match self {
-            TypeHandler::Boolean {
-                return_true,
-                return_false,
-            } => {
-                let text = if value { return_true } else { return_false };
-                let tooltip = if value { "enabled" } else { "disabled" };
+    /// Extract a string from various zvariant::Value types
+    fn extract_string<'a>(value: &'a zvariant::Value) -> Option<Cow<'a, str>> {
+        match value {
+            zvariant::Value::Str(s) => Some(Cow::Borrowed(s.as_str())),
+            zvariant::Value::ObjectPath(p) => Some(Cow::Borrowed(p.as_str())),
+            // Byte strings, also when nested in variants (e.g. "v" holding "ay")
+            zvariant::Value::Array(array)
+                if *array.element_signature() == zvariant::Signature::U8 =>
+            {
+                Self::decode_byte_string(array).map(Cow::Owned)
+            }
+            zvariant::Value::Value(v) => Self::extract_string(v),
+            _ => {
+                log::debug!("warn: Could not extract string from value: {:?}", value);
+                None
+            }
+        }
+    }
 
-                // Use serde_json for proper escaping and formatting
-                let json_output = serde_json::json!({
-                    "text": text,
-                    "tooltip": tooltip
-                });
+    /// Decode an `ay` byte string as UTF-8, without the trailing NUL bytes of C strings
+    fn decode_byte_string(array: &zvariant::Array) -> Option<String> {
+        let mut bytes: Vec<u8> = array
+            .iter()
+            .map(|byte| match byte {
+                zvariant::Value::U8(byte) => Some(*byte),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
 
-                println!("{}", json_output);
-                std::io::stdout()
-                    .flush()
-                    .map_err(|e| format!("Failed to flush stdout: {}", e))
+        String::from_utf8(bytes)
+            .inspect_err(|e| log::debug!("warn: Byte string is not valid UTF-8: {}", e))
+            .ok()
+    }
+
+    /// Extract an integer from various zvariant::Value types
+    fn extract_integer(value: &zvariant::Value) -> Option<i64> {
+        match value {
+            zvariant::Value::U8(n) => Some(i64::from(*n)),
+            zvariant::Value::I16(n) => Some(i64::from(*n)),
+            zvariant::Value::U16(n) => Some(i64::from(*n)),
+            zvariant::Value::I32(n) => Some(i64::from(*n)),
+            zvariant::Value::U32(n) => Some(i64::from(*n)),
+            zvariant::Value::I64(n) => Some(*n),
+            zvariant::Value::U64(n) => i64::try_from(*n)
+                .inspect_err(|_| log::debug!("warn: Integer {} is out of range", n))
+                .ok(),
+            zvariant::Value::Value(v) => Self::extract_integer(v),
+            _ => {
+                log::debug!("warn: Could not extract integer from value: {:?}", value);
+                None
             }
         }
     }
 
-    /// Process a D-Bus message and print formatted output
-    pub fn process_message(&self, message: &zbus::Message) -> Result<(), String> {
-        match self {
-            TypeHandler::Boolean { .. } => {
-                // Try direct boolean deserialization first for efficiency
-                let bool_value = message.body().deserialize::<bool>().or_else(|_| {
-                    // Fall back to generic deserialization and extraction
-                    message
-                        .body()
-                        .deserialize::<zvariant::Value>()
-                        .map_err(|e| format!("Failed to deserialize message: {}", e))
-                        .and_then(|value| {
-                            Self::extract_boolean(&value)
-                                .ok_or_else(|| format!("Could not extract boolean: {:?}", value))
-                        })
-                })?;
+    /// Extract a float from various zvariant::Value types
+    fn extract_float(value: &zvariant::Value) -> Option<f64> {
+        match value {
+            zvariant::Value::F64(n) => Some(*n),
+            zvariant::Value::Value(v) => Self::extract_float(v),
+            _ => {
+                log::debug!("warn: Could not extract float from value: {:?}", value);
+                None
+            }
+        }
+    }
 
-                self.format_and_print_boolean(bool_value)
+    /// Pick the glyph for a value from evenly spaced positions across the range
+    ///
+    /// Values outside the range are clamped to the first or last glyph.
+    fn select_bar(bars: &str, min: i64, max: i64, value: i64) -> char {
+        let glyphs: Vec<char> = bars.chars().collect();
+        // In i128, so ranges spanning most of i64 can't overflow
+        let offset = i128::from(value.clamp(min, max)) - i128::from(min);
+        let position = offset as f64 / (i128::from(max) - i128::from(min)) as f64;
+        let index = (position * (glyphs.len() - 1) as f64).round() as usize;
+        glyphs[index.min(glyphs.len() - 1)]
+    }
+
+    /// Format an integer with a separator between groups of three digits
+    fn group_digits(value: i64, separator: char) -> String {
+        let digits = value.unsigned_abs().to_string();
+        let mut grouped = String::with_capacity(digits.len() * 2);
+        if value < 0 {
+            grouped.push('-');
+        }
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(separator);
             }
+            grouped.push(digit);
         }
+        grouped
     }
 
-    /// Process the raw D-Bus data and print the result
-    pub fn process_and_print(&self, value: &zvariant::Value) -> bool {
+    /// Format a boolean value as Waybar JSON
+    fn format_boolean(&self, value: bool) -> Record {
+        let (return_true, return_false) = match self {
+            TypeHandler::Boolean {
+                return_true,
+                return_false,
+                ..
+            } => (return_true.as_str(), return_false.as_str()),
+            _ => ("true", "false"),
+        };
+
+        let text = if value { return_true } else { return_false };
+        let tooltip = if value { "enabled" } else { "disabled" };
+
+        // Use serde_json for proper escaping and formatting
+        let json_output = serde_json::json!({
+            "text": text,
+            "tooltip": tooltip
+        });
+
+        let tone = if value {
+            Tone::Positive
+        } else {
+            Tone::Negative
+        };
+        Record::new(json_output).with_tone(tone)
+    }
+
+    /// Format a single value as Waybar JSON
+    ///
+    /// Returns `None` if the value should not produce any output.
+    pub fn format_value(
+        &self,
+        value: &zvariant::Value,
+        state: &mut HandlerState,
+    ) -> Result<Option<Record>, String> {
         match self {
-            TypeHandler::Boolean { .. } => {
-                if let Some(b) = Self::extract_boolean(value) {
-                    match self.format_and_print_boolean(b) {
-                        Ok(_) => true,
-                        Err(e) => {
-                            log::debug!("error: {}", e);
-                            false
+            TypeHandler::Boolean { true_value, .. } => {
+                let bool_value = Self::extract_boolean(value, true_value.as_deref())
+                    .ok_or_else(|| format!("Could not extract boolean: {:?}", value))?;
+
+                state.last_boolean = Some(bool_value);
+                Ok(Some(self.format_boolean(bool_value)))
+            }
+            TypeHandler::String {
+                empty_string_output,
+                normalize_whitespace,
+                map_file,
+            } => {
+                let string_value = Self::extract_string(value)
+                    .ok_or_else(|| format!("Could not extract string: {:?}", value))?;
+
+                let normalized;
+                let string_value = if *normalize_whitespace {
+                    normalized = string_value
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    normalized.as_str()
+                } else {
+                    &string_value
+                };
+
+                // Unmapped values are output as-is
+                let string_value = map_file
+                    .as_ref()
+                    .and_then(|map| map.lookup(string_value))
+                    .unwrap_or(string_value);
+
+                let text = match empty_string_output {
+                    Some(placeholder) if string_value.is_empty() => placeholder.as_str(),
+                    _ => string_value,
+                };
+
+                Ok(Some(Record::new(serde_json::json!({ "text": text }))))
+            }
+            TypeHandler::Integer {
+                emit_delta,
+                delta_first_absolute,
+                bars,
+                bars_min,
+                bars_max,
+                map,
+                map_file,
+                with_raw,
+                group_digits,
+                unit,
+                percentage_only,
+            } => {
+                let int_value = Self::extract_integer(value)
+                    .ok_or_else(|| format!("Could not extract integer: {:?}", value))?;
+
+                let shown = if *emit_delta {
+                    match state.previous_integer.replace(int_value) {
+                        Some(previous) => int_value.saturating_sub(previous),
+                        None if *delta_first_absolute => int_value,
+                        None => {
+                            log::debug!("First value {} stored for delta, not emitted", int_value);
+                            return Ok(None);
                         }
                     }
                 } else {
-                    log::debug!("warn: Could not convert value to boolean: {:?}", value);
-                    false
+                    int_value
+                };
+
+                if *percentage_only {
+                    return Ok(Some(Record::new(serde_json::json!({
+                        "text": "",
+                        "percentage": shown.clamp(0, 100)
+                    }))));
+                }
+
+                let label = match (bars, map.as_ref().or(map_file.as_ref())) {
+                    (Some(bars), _) => {
+                        Some(Self::select_bar(bars, *bars_min, *bars_max, shown).to_string())
+                    }
+                    (None, Some(map)) => map.lookup(shown).map(str::to_string),
+                    (None, None) => None,
+                };
+
+                let mut number = match group_digits {
+                    Some(separator) => Self::group_digits(shown, *separator),
+                    None => shown.to_string(),
+                };
+                if let Some(unit) = unit {
+                    number.push_str(unit);
                 }
+
+                // Unmapped values fall back to the raw value, which is never shown twice
+                let text = match (label, with_raw) {
+                    (Some(label), Some(RawPosition::Suffix)) => format!("{} ({})", label, number),
+                    (Some(label), Some(RawPosition::Prefix)) => format!("({}) {}", number, label),
+                    (Some(label), None) => label,
+                    (None, _) => number,
+                };
+
+                Ok(Some(Record::new(serde_json::json!({ "text": text }))))
+            }
+            TypeHandler::Float {
+                precision,
+                float_epsilon,
+            } => {
+                let float_value = Self::extract_float(value)
+                    .ok_or_else(|| format!("Could not extract float: {:?}", value))?;
+
+                // Values within the epsilon keep the previous one, so noise produces
+                // identical records for --dedup, --stable-count and --poll-backup
+                let shown = match state.previous_float {
+                    Some(previous) if (float_value - previous).abs() <= *float_epsilon => {
+                        log::debug!("Value {} within epsilon of {}", float_value, previous);
+                        previous
+                    }
+                    _ => {
+                        state.previous_float = Some(float_value);
+                        float_value
+                    }
+                };
+
+                let text = match precision {
+                    Some(precision) => format!("{:.*}", precision, shown),
+                    None => shown.to_string(),
+                };
+                Ok(Some(Record::new(serde_json::json!({ "text": text }))))
+            }
+        }
+    }
+
+    /// Format a D-Bus message as Waybar JSON
+    ///
+    /// Returns `None` if the message should not produce any output.
+    pub fn format_message(
+        &self,
+        message: &zbus::Message,
+        state: &mut HandlerState,
+    ) -> Result<Option<Record>, String> {
+        if let TypeHandler::Boolean { .. } = self {
+            // Try direct boolean deserialization first for efficiency
+            if let Ok(bool_value) = message.body().deserialize::<bool>() {
+                state.last_boolean = Some(bool_value);
+                return Ok(Some(self.format_boolean(bool_value)));
+            }
+        }
+
+        // Fall back to generic deserialization and extraction
+        let value = selector::first_argument(message)?;
+        self.format_value(&value, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::collections::HashMap;
+    use zvariant::Value;
+
+    /// Parse a type handler from its command line arguments
+    fn handler(args: &[&str]) -> TypeHandler {
+        let handler = Config::try_parse_from(test_support::argv(args))
+            .unwrap()
+            .type_handler;
+        handler.validate().unwrap();
+        handler
+    }
+
+    /// Text emitted for each value in turn, `None` for suppressed values
+    fn texts(handler: &TypeHandler, values: &[Value]) -> Vec<Option<String>> {
+        let mut state = HandlerState::default();
+        values
+            .iter()
+            .map(|value| {
+                let record = handler.format_value(value, &mut state).unwrap();
+                record.map(|record| record.json["text"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    fn text(handler: &TypeHandler, value: &Value) -> String {
+        let record = handler.format_value(value, &mut HandlerState::default());
+        record.unwrap().unwrap().json["text"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn empty_string_output_replaces_only_empty_strings() {
+        let handler = handler(&["string", "--empty-string-output", "-"]);
+        assert_eq!(text(&handler, &Value::from("")), "-");
+        assert_eq!(text(&handler, &Value::from("x")), "x");
+        assert_eq!(text(&handler, &Value::from(" ")), " ");
+    }
+
+    #[test]
+    fn empty_string_is_emitted_without_empty_string_output() {
+        let handler = handler(&["string"]);
+        assert_eq!(text(&handler, &Value::from("")), "");
+        assert_eq!(text(&handler, &Value::from("x")), "x");
+    }
+
+    #[test]
+    fn dict_values_chain_into_the_extractors() {
+        let value = Value::from(HashMap::from([
+            ("Active", Value::from(true)),
+            ("Level", Value::from(42u32)),
+        ]));
+        let select = |key: &str| {
+            selector::select_keys(&value, &[key.to_string()])
+                .unwrap()
+                .unwrap()
+        };
+
+        assert_eq!(text(&handler(&["boolean"]), select("Active")), "true");
+        assert_eq!(text(&handler(&["integer"]), select("Level")), "42");
+    }
+
+    fn integers(values: &[i64]) -> Vec<Value<'static>> {
+        values.iter().map(|&value| Value::from(value)).collect()
+    }
+
+    fn some(texts: &[&str]) -> Vec<Option<String>> {
+        texts.iter().map(|text| Some(text.to_string())).collect()
+    }
+
+    #[test]
+    fn emit_delta_of_increasing_values() {
+        let handler = handler(&["integer", "--emit-delta"]);
+        let mut expected = vec![None];
+        expected.extend(some(&["5", "1", "0"]));
+        assert_eq!(texts(&handler, &integers(&[10, 15, 16, 16])), expected);
+    }
+
+    #[test]
+    fn emit_delta_of_decreasing_values() {
+        let handler = handler(&["integer", "--emit-delta"]);
+        let mut expected = vec![None];
+        expected.extend(some(&["-3", "-17"]));
+        assert_eq!(texts(&handler, &integers(&[10, 7, -10])), expected);
+    }
+
+    #[test]
+    fn emit_delta_with_absolute_first_value() {
+        let handler = handler(&["integer", "--emit-delta", "--delta-first-absolute"]);
+        let expected = some(&["10", "-4", "6"]);
+        assert_eq!(texts(&handler, &integers(&[10, 6, 12])), expected);
+    }
+
+    #[test]
+    fn emit_delta_saturates_instead_of_overflowing() {
+        let handler = handler(&["integer", "--emit-delta"]);
+        let values = integers(&[i64::MIN, i64::MAX]);
+        assert_eq!(
+            texts(&handler, &values),
+            vec![None, Some(i64::MAX.to_string())]
+        );
+    }
+
+    #[test]
+    fn bars_spread_evenly_and_clamp() {
+        let handler = handler(&["integer", "--bars", "abc"]);
+        let bars: Vec<String> = [-5, 0, 49, 50, 100, 500]
+            .iter()
+            .map(|&value| text(&handler, &Value::from(value as i64)))
+            .collect();
+        assert_eq!(bars, ["a", "a", "b", "b", "c", "c"]);
+    }
+
+    #[test]
+    fn bars_over_the_full_integer_range() {
+        let handler = handler(&[
+            "integer",
+            "--bars",
+            "abc",
+            "--bars-min",
+            "-9223372036854775808",
+            "--bars-max",
+            "9223372036854775807",
+        ]);
+        assert_eq!(text(&handler, &Value::from(i64::MIN)), "a");
+        assert_eq!(text(&handler, &Value::from(0i64)), "b");
+        assert_eq!(text(&handler, &Value::from(i64::MAX)), "c");
+    }
+
+    #[test]
+    fn normalize_whitespace_collapses_tabs_spaces_and_newlines() {
+        let handler = handler(&["string", "--normalize-whitespace"]);
+        let value = Value::from("\t Now  playing:\t\tSong \n by\r\nArtist  ");
+        assert_eq!(text(&handler, &value), "Now playing: Song by Artist");
+    }
+
+    #[test]
+    fn normalize_whitespace_turns_blank_values_empty() {
+        let handler = handler(&[
+            "string",
+            "--normalize-whitespace",
+            "--empty-string-output",
+            "-",
+        ]);
+        assert_eq!(text(&handler, &Value::from(" \t\n ")), "-");
+    }
+
+    #[test]
+    fn whitespace_is_kept_without_normalize_whitespace() {
+        let handler = handler(&["string"]);
+        assert_eq!(text(&handler, &Value::from(" a\tb ")), " a\tb ");
+    }
+
+    #[test]
+    fn with_raw_adds_the_value_to_mapped_labels() {
+        let map = ["integer", "--map", "..30=low,31..70=mid"];
+        let suffix = handler(&[&map[..], &["--with-raw"]].concat());
+        assert_eq!(text(&suffix, &Value::from(45i64)), "mid (45)");
+
+        let prefix = handler(&[&map[..], &["--with-raw", "prefix"]].concat());
+        assert_eq!(text(&prefix, &Value::from(45i64)), "(45) mid");
+    }
+
+    #[test]
+    fn with_raw_shows_unmapped_values_once() {
+        let handler = handler(&["integer", "--map", "..30=low", "--with-raw"]);
+        assert_eq!(text(&handler, &Value::from(99i64)), "99");
+    }
+
+    #[test]
+    fn with_raw_adds_the_value_to_bars() {
+        let handler = handler(&["integer", "--bars", "ab", "--with-raw", "--unit", "%"]);
+        assert_eq!(text(&handler, &Value::from(80i64)), "b (80%)");
+    }
+
+    #[test]
+    fn with_raw_requires_a_mapping() {
+        let config = Config::try_parse_from(test_support::argv(&["integer", "--with-raw"]));
+        let error = config.unwrap().type_handler.validate().unwrap_err();
+        assert!(error.contains("--with-raw requires"), "{}", error);
+    }
+
+    /// Wrap a value in `depth` variant layers
+    fn nested(value: Value<'static>, depth: usize) -> Value<'static> {
+        (0..depth).fold(value, |value, _| Value::Value(Box::new(value)))
+    }
+
+    const DEPTHS: [usize; 4] = [0, 1, 2, 3];
+
+    #[test]
+    fn extract_boolean_through_nested_variants() {
+        for depth in DEPTHS {
+            let boolean = |value| TypeHandler::extract_boolean(&nested(value, depth), None);
+            assert_eq!(boolean(Value::from(true)), Some(true), "depth {}", depth);
+            assert_eq!(boolean(Value::from(false)), Some(false), "depth {}", depth);
+
+            let string = |value| TypeHandler::extract_boolean(&nested(value, depth), Some("on"));
+            assert_eq!(string(Value::from("on")), Some(true), "depth {}", depth);
+            assert_eq!(string(Value::from("off")), Some(false), "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn extract_boolean_rejects_other_types() {
+        for depth in DEPTHS {
+            for value in [Value::from(1i32), Value::from(1.0), Value::from("true")] {
+                let value = nested(value, depth);
+                assert_eq!(
+                    TypeHandler::extract_boolean(&value, None),
+                    None,
+                    "{:?}",
+                    value
+                );
+            }
+            let value = nested(Value::from(1u8), depth);
+            assert_eq!(TypeHandler::extract_boolean(&value, Some("1")), None);
+        }
+    }
+
+    #[test]
+    fn extract_integer_through_nested_variants() {
+        for depth in DEPTHS {
+            let values = [
+                (Value::from(200u8), 200),
+                (Value::from(-300i16), -300),
+                (Value::from(60000u16), 60000),
+                (Value::from(-70000i32), -70000),
+                (Value::from(4_000_000_000u32), 4_000_000_000),
+                (Value::from(i64::MIN), i64::MIN),
+                (Value::from(i64::MAX as u64), i64::MAX),
+            ];
+            for (value, expected) in values {
+                let value = nested(value, depth);
+                assert_eq!(
+                    TypeHandler::extract_integer(&value),
+                    Some(expected),
+                    "{:?}",
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn extract_integer_rejects_other_types() {
+        for depth in DEPTHS {
+            let values = [
+                Value::from(true),
+                Value::from(1.5),
+                Value::from("1"),
+                Value::from(u64::MAX),
+            ];
+            for value in values {
+                let value = nested(value, depth);
+                assert_eq!(TypeHandler::extract_integer(&value), None, "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn extract_float_through_nested_variants() {
+        for depth in DEPTHS {
+            for expected in [0.0, -1.5, 21.25, f64::MAX] {
+                let value = nested(Value::from(expected), depth);
+                assert_eq!(
+                    TypeHandler::extract_float(&value),
+                    Some(expected),
+                    "{:?}",
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn extract_float_rejects_other_types() {
+        for depth in DEPTHS {
+            for value in [Value::from(true), Value::from(1i32), Value::from("1.5")] {
+                let value = nested(value, depth);
+                assert_eq!(TypeHandler::extract_float(&value), None, "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn extract_string_through_nested_variants() {
+        for depth in DEPTHS {
+            let string =
+                |value| TypeHandler::extract_string(&nested(value, depth)).map(Cow::into_owned);
+            assert_eq!(string(Value::from("ä")).as_deref(), Some("ä"));
+            assert_eq!(string(Value::from("")).as_deref(), Some(""));
+            let path = zvariant::ObjectPath::try_from("/org/example").unwrap();
+            assert_eq!(string(Value::from(path)).as_deref(), Some("/org/example"));
+        }
+    }
+
+    #[test]
+    fn extract_string_rejects_other_types() {
+        for depth in DEPTHS {
+            let values = [
+                Value::from(true),
+                Value::from(1i32),
+                Value::from(1.5),
+                Value::from(vec!["a", "b"]),
+            ];
+            for value in values {
+                let value = nested(value, depth);
+                assert_eq!(TypeHandler::extract_string(&value), None, "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn format_message_reads_bare_and_struct_bodies_alike() {
+        let handler = handler(&["boolean", "--return-true", "on", "--return-false", "off"]);
+        let signal = || zbus::Message::signal("/", "a.b", "X").unwrap();
+        let bodies = [
+            signal().build(&(true,)).unwrap(),
+            signal().build(&((true,),)).unwrap(),
+        ];
+        for message in bodies {
+            let mut state = HandlerState::default();
+            let record = handler
+                .format_message(&message, &mut state)
+                .unwrap()
+                .unwrap();
+            assert_eq!(record.json["text"], "on", "{}", message.body().signature());
+            assert_eq!(state.last_boolean, Some(true));
+        }
+    }
+
+    #[test]
+    fn group_digits_at_every_magnitude() {
+        let cases = [
+            (0, "0"),
+            (7, "7"),
+            (999, "999"),
+            (1000, "1,000"),
+            (12345, "12,345"),
+            (123456, "123,456"),
+            (1234567, "1,234,567"),
+            (i64::MAX, "9,223,372,036,854,775,807"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(TypeHandler::group_digits(value, ','), expected);
+        }
+    }
+
+    #[test]
+    fn group_digits_of_negative_numbers() {
+        let cases = [
+            (-1, "-1"),
+            (-999, "-999"),
+            (-1000, "-1,000"),
+            (-123456, "-123,456"),
+            (i64::MIN, "-9,223,372,036,854,775,808"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(TypeHandler::group_digits(value, ','), expected);
+        }
+    }
+
+    #[test]
+    fn group_digits_with_a_custom_separator() {
+        let grouped = handler(&["integer", "--group-digits"]);
+        assert_eq!(text(&grouped, &Value::from(-1234567i32)), "-1,234,567");
+        let grouped = handler(&["integer", "--group-digits", " "]);
+        assert_eq!(text(&grouped, &Value::from(1234567u32)), "1 234 567");
+    }
+
+    /// Parse, resolve and validate a configuration with a string handler
+    fn checked(args: &[&str]) -> Result<(), String> {
+        let args = test_support::argv(&[args, &["string"]].concat());
+        let mut config = Config::try_parse_from(args).unwrap();
+        config.resolve().and_then(|_| config.validate())
+    }
+
+    #[test]
+    fn max_reconnects_requires_reconnecting() {
+        let error = checked(&["--max-reconnects", "3"]).unwrap_err();
+        assert!(error.contains("requires --reconnect"), "{}", error);
+        let error = checked(&["--reconnect", "--max-reconnects", "0"]).unwrap_err();
+        assert!(error.contains("at least 1"), "{}", error);
+        checked(&["--reconnect", "--max-reconnects", "3"]).unwrap();
+        checked(&["--never-exit", "--max-reconnects", "3"]).unwrap();
+    }
+
+    /// The service, path, interface and property of a parsed status query
+    fn status(status: &str) -> [String; 4] {
+        let status = StatusConfig::parse(status).unwrap();
+        [
+            status.service,
+            status.object_path,
+            status.interface,
+            status.property,
+        ]
+    }
+
+    #[test]
+    fn status_with_a_slash_between_service_and_path() {
+        assert_eq!(
+            status("org.example.Svc/org/example a.b.C Level"),
+            ["org.example.Svc", "/org/example", "a.b.C", "Level"]
+        );
+        assert_eq!(status("a.b/ a.b.C Level")[1], "/");
+    }
+
+    #[test]
+    fn status_with_whitespace_between_service_and_path() {
+        assert_eq!(
+            status("  org.example.Svc   /org/example\ta.b.C Level "),
+            ["org.example.Svc", "/org/example", "a.b.C", "Level"]
+        );
+    }
+
+    #[test]
+    fn status_with_dotted_and_unique_service_names() {
+        let service_path = "org.freedesktop.systemd1/org/freedesktop/systemd1/unit/a_2eservice";
+        let [service, path, ..] = status(&format!("{} a.b.C ActiveState", service_path));
+        assert_eq!(service, "org.freedesktop.systemd1");
+        assert_eq!(path, "/org/freedesktop/systemd1/unit/a_2eservice");
+
+        let [service, path, ..] = status(":1.42 /org/example a.b.C Level");
+        assert_eq!((service.as_str(), path.as_str()), (":1.42", "/org/example"));
+    }
+
+    #[test]
+    fn status_rejects_malformed_queries() {
+        let cases = [
+            (
+                "a.b.Svc a.b.C Level",
+                "First parameter must be 'service/path'",
+            ),
+            ("a.b.Svc/a a.b.C", "Invalid status format"),
+            ("a.b.Svc/a a.b.C Level Extra", "Invalid status format"),
+            ("a.b.Svc a a.b.C Level", "Invalid status format"),
+            ("/org/example a.b.C Level", "Service name cannot be empty"),
+            ("a.b.1Svc/org/example a.b.C Level", "Invalid service name"),
+            ("a.b.Svc/org//example a.b.C Level", "Invalid object path"),
+            ("a.b.Svc /org/example/ a.b.C Level", "Invalid object path"),
+            ("a.b.Svc/org/example Interface Level", "Invalid interface"),
+            ("", "Invalid status format"),
+        ];
+        for (status, expected) in cases {
+            let error = StatusConfig::parse(status).unwrap_err();
+            assert!(error.contains(expected), "{:?}: {}", status, error);
+        }
+    }
+
+    #[test]
+    fn flags_accept_boolish_environment_values() {
+        if !test_support::in_child() {
+            // Setting variables would affect other tests, so parse in a child process
+            let output =
+                test_support::child_test("cli::tests::flags_accept_boolish_environment_values")
+                    .env("WDM_COLOR", "1")
+                    .env("WDM_DEDUP", "0")
+                    .env("WDM_RECONNECT", "yes")
+                    .env("WDM_NEVER_EXIT", "off")
+                    .env("WDM_NORMALIZE_WHITESPACE", "on")
+                    .output()
+                    .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            return;
+        }
+
+        let config = Config::try_parse_from(test_support::argv(&["string"])).unwrap();
+        assert!(config.color);
+        assert!(!config.dedup);
+        assert!(config.reconnect);
+        assert!(!config.never_exit);
+        assert!(matches!(
+            config.type_handler,
+            TypeHandler::String {
+                normalize_whitespace: true,
+                ..
             }
+        ));
+    }
+
+    /// An `ay` byte string
+    fn bytes(bytes: &[u8]) -> Value<'static> {
+        Value::from(bytes.to_vec())
+    }
+
+    #[test]
+    fn byte_strings_are_arrays_of_bytes() {
+        let Value::Array(array) = bytes(b"abc") else {
+            panic!("not an array");
+        };
+        assert_eq!(array.element_signature().to_string(), "y");
+    }
+
+    #[test]
+    fn variant_wrapped_byte_strings_decode_as_utf8() {
+        let value = Value::Value(Box::new(bytes("héllo".as_bytes())));
+        assert_eq!(text(&handler(&["string"]), &value), "héllo");
+    }
+
+    #[test]
+    fn byte_strings_through_nested_variants() {
+        for depth in DEPTHS {
+            let string =
+                |value| TypeHandler::extract_string(&nested(value, depth)).map(Cow::into_owned);
+            assert_eq!(string(bytes(b"wlan0")).as_deref(), Some("wlan0"));
+            assert_eq!(string(bytes(b"")).as_deref(), Some(""));
         }
     }
+
+    #[test]
+    fn byte_strings_drop_trailing_nul_bytes() {
+        let string = |value| TypeHandler::extract_string(&value).map(Cow::into_owned);
+        assert_eq!(string(bytes(b"wlan0\0")).as_deref(), Some("wlan0"));
+        assert_eq!(string(bytes(b"wlan0\0\0\0")).as_deref(), Some("wlan0"));
+        assert_eq!(string(bytes(b"\0")).as_deref(), Some(""));
+        assert_eq!(string(bytes(b"a\0b")).as_deref(), Some("a\0b"));
+    }
+
+    #[test]
+    fn byte_strings_must_be_utf8() {
+        let value = Value::Value(Box::new(bytes(&[0x66, 0xff, 0x6f])));
+        assert_eq!(TypeHandler::extract_string(&value), None);
+    }
+
+    #[test]
+    fn help_lists_the_other_commands() {
+        use clap::CommandFactory;
+
+        let help = Config::command().render_help().to_string();
+        assert!(help.contains("  selftest "), "{}", help);
+        assert!(help.contains("  properties "), "{}", help);
+    }
+
+    /// Settings shown by --show-config for the given options
+    fn settings(args: &[&str]) -> serde_json::Value {
+        let mut config =
+            Config::try_parse_from(["waybar-dbus-monitor"].iter().chain(args)).unwrap();
+        config.resolve().unwrap();
+        config.validate().unwrap();
+        config.settings()
+    }
+
+    #[test]
+    fn settings_include_presets_and_handler_defaults() {
+        let settings = settings(&["--systemd-unit", "foo.service", "boolean"]);
+        assert_eq!(settings["bus"], "system");
+        assert_eq!(settings["interface"], "org.freedesktop.systemd1.Unit");
+        assert_eq!(settings["status"]["query"]["property"], "ActiveState");
+        assert_eq!(settings["monitors"][0]["member"], "ActiveState");
+        let handler = &settings["monitors"][0]["handler"];
+        assert_eq!(handler["type"], "boolean");
+        assert_eq!(handler["return_true"], "true");
+        assert_eq!(handler["true_value"], "active");
+        assert_eq!(settings["output"]["format"], "json");
+        assert_eq!(settings["output"]["delimiter"], "newline");
+        assert_eq!(settings["output"]["buffer"], 8);
+    }
+
+    #[test]
+    fn settings_show_options_in_their_command_line_form() {
+        let settings = settings(&[
+            "--interface",
+            "a.b",
+            "--monitor",
+            "X",
+            "--json-field-map",
+            "text=label",
+            "--delimiter",
+            "nul",
+            "integer",
+            "--map",
+            "..30=low,31..70=mid,71=top",
+        ]);
+        let handler = &settings["monitors"][0]["handler"];
+        assert_eq!(handler["map"], "..30=low,31..70=mid,71=top");
+        assert_eq!(handler["bars_min"], serde_json::Value::Null);
+        assert_eq!(settings["output"]["json_field_map"], "text=label");
+        assert_eq!(settings["output"]["delimiter"], "nul");
+        // Defaults of options that depend on an unset one don't apply
+        assert_eq!(settings["values"]["age_format"], serde_json::Value::Null);
+    }
 }
@@ -0,0 +1,182 @@
+/// Monitor definitions loaded from a TOML configuration file
+///
+/// Each `[[monitor]]` table names a D-Bus member and the type handler used for
+/// it. Handlers are written as the same arguments the command line accepts,
+/// so they are parsed and validated exactly like the CLI handler:
+///
+/// ```toml
+/// [[monitor]]
+/// member = "BatteryLevel"
+/// handler = ["integer", "--bars", "▁▂▃▄▅▆▇"]
+/// ```
+use crate::cli::TypeHandler;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::path::Path;
+use toml_edit::{DocumentMut, Table};
+
+/// A monitored member with its own type handler
+#[derive(Debug)]
+pub struct MonitorOverride {
+    pub member: String,
+    pub handler: TypeHandler,
+}
+
+/// Wrapper to parse a handler from its command line arguments
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true)]
+struct HandlerArgs {
+    #[command(subcommand)]
+    handler: TypeHandler,
+}
+
+/// Parse a handler from its arguments, ignoring the `WDM_` environment variables
+///
+/// The variables configure the command line handler. Applying them to the
+/// handlers of the file as well would change every one of them.
+fn parse_handler(handler_args: Vec<String>) -> Result<TypeHandler, clap::Error> {
+    let mut command = HandlerArgs::command();
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    for name in names {
+        command =
+            command.mut_subcommand(name, |subcommand| subcommand.mut_args(|arg| arg.env(None)));
+    }
+
+    let matches = command.try_get_matches_from(handler_args)?;
+    Ok(HandlerArgs::from_arg_matches(&matches)?.handler)
+}
+
+/// Load the monitor definitions from a configuration file
+pub fn load(path: &Path) -> Result<Vec<MonitorOverride>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file '{}': {}", path.display(), e))?;
+
+    parse(&contents).map_err(|e| format!("Invalid config file '{}': {}", path.display(), e))
+}
+
+/// Parse the monitor definitions from TOML
+fn parse(contents: &str) -> Result<Vec<MonitorOverride>, String> {
+    let document: DocumentMut = contents.parse().map_err(|e| format!("{}", e))?;
+
+    for (key, _) in document.iter() {
+        if key != "monitor" {
+            return Err(format!("Unknown key '{}'", key));
+        }
+    }
+
+    let Some(item) = document.get("monitor") else {
+        return Ok(Vec::new());
+    };
+    let tables = item
+        .as_array_of_tables()
+        .ok_or_else(|| "'monitor' must be an array of tables ([[monitor]])".to_string())?;
+
+    tables
+        .iter()
+        .enumerate()
+        .map(|(i, table)| parse_monitor(table).map_err(|e| format!("monitor #{}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Parse a single `[[monitor]]` table
+fn parse_monitor(table: &Table) -> Result<MonitorOverride, String> {
+    for (key, _) in table.iter() {
+        if key != "member" && key != "handler" {
+            return Err(format!("Unknown key '{}'", key));
+        }
+    }
+
+    let member = table
+        .get("member")
+        .and_then(|item| item.as_str())
+        .ok_or_else(|| "'member' must be a string".to_string())?
+        .to_string();
+
+    let handler_args = table
+        .get("handler")
+        .and_then(|item| item.as_array())
+        .ok_or_else(|| "'handler' must be an array of strings".to_string())?
+        .iter()
+        .map(|arg| arg.as_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| "'handler' must be an array of strings".to_string())?;
+
+    let handler = parse_handler(handler_args)
+        .map_err(|e| format!("Invalid handler for '{}': {}", member, e.render()))?;
+    handler.validate()?;
+
+    Ok(MonitorOverride { member, handler })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn handlers_ignore_the_environment() {
+        if !test_support::in_child() {
+            // Setting variables would affect other tests, so parse in a child process
+            let output =
+                test_support::child_test("config_file::tests::handlers_ignore_the_environment")
+                    .env("WDM_RETURN_TRUE", "ENVLEAK")
+                    .env("WDM_MAP", "x=ENVLEAK")
+                    .output()
+                    .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            return;
+        }
+
+        let monitors = parse(
+            r#"
+            [[monitor]]
+            member = "A"
+            handler = ["boolean"]
+
+            [[monitor]]
+            member = "B"
+            handler = ["string"]
+            "#,
+        )
+        .unwrap();
+        let handlers = format!("{:?}", monitors);
+        assert!(!handlers.contains("ENVLEAK"), "{}", handlers);
+    }
+
+    #[test]
+    fn handlers_are_parsed_like_the_command_line() {
+        let monitors = parse(
+            r#"
+            [[monitor]]
+            member = "Level"
+            handler = ["integer", "--bars", "▁▂▃"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(monitors[0].member, "Level");
+        assert!(matches!(
+            &monitors[0].handler,
+            TypeHandler::Integer { bars: Some(bars), .. } if bars == "▁▂▃"
+        ));
+    }
+
+    #[test]
+    fn invalid_handlers_name_the_monitor() {
+        let error = parse(
+            r#"
+            [[monitor]]
+            member = "Level"
+            handler = ["integer", "--no-such-option"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            error.contains("monitor #1: Invalid handler for 'Level'"),
+            "{}",
+            error
+        );
+    }
+}
@@ -1,141 +1,1059 @@
-use crate::cli::Config;
+use crate::cli::{
+    BusType, Config, EmitSignalConfig, HandlerState, MessageType, StatusConfig, UnexpectedPolicy,
+};
 use crate::error::AppError;
+use crate::log_throttle::LogThrottle;
+use crate::output::{self, Record, Tone};
+use crate::preset;
+use crate::recording::{self, Recorder};
 use crate::retry::{RetryConfig, retry_operation, retry_operation_with_config};
-use crate::{error_message_processing, error_not_found, report_error};
+use crate::selector;
+use crate::timer::{self, Ticker};
+use crate::{error_message_processing, error_not_found, error_service_unavailable, report_error};
 use futures_lite::stream::StreamExt;
 use log::debug;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime};
+use zbus::zvariant::{OwnedValue, Value};
 use zbus::{Connection, MatchRule, MessageStream, Proxy};
 
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// Interval for checking the config file for changes with --watch-file
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct DBusListener {
     pub config: Config,
+    /// Handler state per monitored member
+    states: RefCell<HashMap<String, HandlerState>>,
+    /// Latest value of the --percentage-status property
+    percentage: Cell<Option<i64>>,
+    /// Last emitted record, re-emitted when the percentage is refreshed on its own
+    last_record: RefCell<Option<Record>>,
+    /// Last value of the --status property, queried or signalled, for --poll-backup
+    last_status_value: RefCell<Option<OwnedValue>>,
+    /// Recording of received messages for --record, kept across reconnects
+    recorder: RefCell<Option<Recorder>>,
+    /// Latest value with the number of consecutive times it was seen, for --stable-count
+    stable_candidate: RefCell<Option<(Record, usize)>>,
+    /// When the emitted value last changed, for --show-age
+    last_change: Cell<Option<Instant>>,
+    /// When the last value was emitted, changed or not, for --stale-after
+    last_update: Cell<Option<Instant>>,
+    /// Number of values emitted on the current connection, for --status-defer
+    emitted_values: Cell<u64>,
+    /// Whether the last value is shown as stale
+    stale: Cell<bool>,
+    /// Texts waiting to be sent as --emit-signal signals
+    pending_signals: RefCell<Vec<String>>,
+    /// When to emit the --cleared-output, set by --clear-after-from-arg
+    clear_deadline: Cell<Option<Instant>>,
+    /// Rate limiting of errors logged for received messages, for --log-throttle
+    log_throttle: RefCell<LogThrottle>,
+    /// --interface and its --interface-fallback names, negotiated on every connection
+    interface_candidates: Vec<String>,
 }
 
 impl DBusListener {
-    pub fn new(config: Config) -> Self {
-        Self { config }
+    pub fn new(config: Config, recorder: Option<Recorder>) -> Self {
+        Self {
+            states: RefCell::new(HashMap::new()),
+            percentage: Cell::new(None),
+            last_record: RefCell::new(None),
+            last_status_value: RefCell::new(None),
+            recorder: RefCell::new(recorder),
+            pending_signals: RefCell::new(Vec::new()),
+            last_change: Cell::new(None),
+            last_update: Cell::new(None),
+            emitted_values: Cell::new(0),
+            stale: Cell::new(false),
+            stable_candidate: RefCell::new(None),
+            clear_deadline: Cell::new(None),
+            interface_candidates: config.interface_candidates(),
+            log_throttle: RefCell::new(LogThrottle::new(
+                config.log_throttle.map(Duration::from_millis),
+            )),
+            config,
+        }
+    }
+
+    /// Listen for D-Bus signals, reconnecting with backoff if enabled
+    pub async fn run(&mut self) -> Result<(), AppError> {
+        if !self.config.reconnect && !self.config.never_exit {
+            return self.listen().await;
+        }
+
+        let retry_config = RetryConfig::default();
+        let mut attempt = 0;
+        // Reconnections in a row, tracked apart from the backoff attempt
+        let mut reconnects = 0;
+
+        loop {
+            let started = Instant::now();
+            let result = self.listen().await;
+
+            match &result {
+                Err(e) if e.is_permanent() && !self.config.never_exit => return result,
+                Err(e) if e.is_permanent() => {
+                    debug!("error: Fatal error, restarting due to --never-exit: {}", e);
+                    output::emit_error_once(e);
+                }
+                Err(e) => debug!("warn: Connection lost: {}", e),
+                Ok(()) => debug!("warn: Message stream ended"),
+            }
+
+            // A connection that stayed up for a while starts a fresh backoff sequence
+            if started.elapsed() >= Duration::from_millis(retry_config.max_delay_ms) {
+                attempt = 0;
+                reconnects = 0;
+            }
+
+            if self.config.max_reconnects == Some(reconnects) {
+                return self.reconnects_exhausted(result).await;
+            }
+            reconnects += 1;
+
+            // Always wait before reconnecting, so a failing bus can't cause a busy loop
+            let delay = retry_config.delay_for_attempt(attempt);
+            debug!("Reconnecting after {:?} delay", delay);
+            timer::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Enter the terminal state after --max-reconnects reconnections failed
+    ///
+    /// Without --reconnect-exhausted-output, the last error is returned so its
+    /// code is emitted on exit.
+    async fn reconnects_exhausted(&self, result: Result<(), AppError>) -> Result<(), AppError> {
+        debug!(
+            "error: Giving up after {:?} reconnections",
+            self.config.max_reconnects
+        );
+
+        let result = match &self.config.reconnect_exhausted_output {
+            Some(text) => {
+                if let Err(e) = output::emit_json(&serde_json::json!({ "text": text })) {
+                    debug!("error: {}", e);
+                }
+                Ok(())
+            }
+            None => result,
+        };
+
+        if !self.config.hold_when_exhausted {
+            return result;
+        }
+        if let Err(e) = &result {
+            output::emit_error_once(e);
+        }
+        debug!("Holding the terminal state");
+        std::future::pending().await
+    }
+
+    /// Listen for the monitored messages for a while and report what was received
+    ///
+    /// Returns whether at least one matching message arrived.
+    pub async fn warmup_check(&self, duration: Duration) -> Result<bool, AppError> {
+        let connection = retry_operation_with_config(
+            || async { establish_connection(self.config.bus).await },
+            "D-Bus connection",
+            RetryConfig::default(),
+        )
+        .await?;
+        let mut stream = self.create_message_stream(&connection).await?;
+
+        debug!(
+            "Listening {}s for {} {:?}",
+            duration.as_secs(),
+            self.config.interface(),
+            self.config.monitors()
+        );
+
+        // Count received messages per member and payload signature
+        let mut shapes: BTreeMap<(String, String), usize> = BTreeMap::new();
+        let deadline = Instant::now() + duration;
+
+        while let Some(Some(msg)) = timer::timeout_at(deadline, stream.next()).await {
+            let message = msg.map_err(AppError::from)?;
+            let header = message.header();
+            let member = header.member().map(|m| m.to_string()).unwrap_or_default();
+            if !self.config.properties_changed && !self.config.monitors().contains(&member.as_str())
+            {
+                continue;
+            }
+
+            let signature = message.body().signature().to_string();
+            *shapes.entry((member, signature)).or_default() += 1;
+        }
+
+        // The result is a record like any value, so it is framed and flushed the same way
+        let passed = !shapes.is_empty();
+        let tooltip = if passed {
+            let counts: Vec<String> = shapes
+                .iter()
+                .map(|((member, signature), count)| {
+                    format!("{} ({}): {}", member, signature, count)
+                })
+                .collect();
+            format!("Received messages:\n{}", counts.join("\n"))
+        } else {
+            "No matching message received".to_string()
+        };
+        let (text, tone) = if passed {
+            ("PASS", Tone::Positive)
+        } else {
+            ("FAIL", Tone::Negative)
+        };
+        let record = Record::new(serde_json::json!({ "text": text, "tooltip": tooltip }));
+        output::emit_value(record.with_tone(tone))
+            .await
+            .map_err(|e| error_message_processing!("{}", e))?;
+        Ok(passed)
     }
 
     /// Establish connection and listen for D-Bus signals with retry logic
-    pub async fn listen(&self) -> Result<(), AppError> {
+    pub async fn listen(&mut self) -> Result<(), AppError> {
+        // No value is known until the first one is emitted, which replaces this state
+        if let Some(class) = &self.config.initializing_class {
+            debug!("Emitting initializing state");
+            if let Err(e) = output::emit_json(&serde_json::json!({ "text": "", "class": class })) {
+                debug!("error: {}", e);
+            }
+        }
+
         // Use default retry configuration for connection
         let connection = retry_operation_with_config(
-            || async { self.establish_connection().await },
+            || async { establish_connection(self.config.bus).await },
             "D-Bus connection",
             RetryConfig::default(),
         )
         .await?;
 
-        // --- PHASE 1: Initial State Query ---
-        if let Some(status_config) = match self.config.parse_status() {
+        // Values from a previous connection must not leak into this one
+        self.states.borrow_mut().clear();
+        self.last_record.replace(None);
+        self.last_status_value.replace(None);
+        self.percentage.set(None);
+        self.pending_signals.borrow_mut().clear();
+        self.last_change.set(None);
+        self.last_update.set(None);
+        self.emitted_values.set(0);
+        self.stale.set(false);
+        self.stable_candidate.replace(None);
+        self.clear_deadline.set(None);
+
+        if let Some(on_connect_output) = &self.config.on_connect_output {
+            debug!("Emitting on-connect output");
+            if let Err(e) = output::emit_json(&serde_json::json!({ "text": on_connect_output })) {
+                debug!("error: {}", e);
+            }
+        }
+
+        if self.config.systemd_unit.is_some() {
+            preset::subscribe_systemd(&connection)
+                .await
+                .unwrap_or_else(|e| debug!("warn: Failed to subscribe to systemd signals: {}", e));
+        }
+        if self.config.preset.is_some() {
+            preset::check_preset(&connection, &self.config)
+                .await
+                .map_err(|e| error_service_unavailable!("{}", e))?;
+        }
+
+        let mut status_config = match self.config.parse_status() {
             Ok(config) => config,
             Err(e) => {
                 debug!("error: Failed to parse status configuration: {}", e);
                 return Err(error_not_found!("Invalid status format: {}", e));
             }
-        } {
-            // Wrap the property query in retry for service availability
-            let initial_state_result = retry_operation_with_config(
-                || async {
-                    let proxy = Proxy::new(
-                        &connection,
-                        status_config.service.as_str(),
-                        status_config.object_path.as_str(),
-                        status_config.interface.as_str(),
-                    )
-                    .await?;
+        };
 
-                    let value = proxy
-                        .get_property::<zbus::zvariant::Value>(&status_config.property)
-                        .await?;
+        if self.interface_candidates.len() > 1 {
+            let interface = self
+                .negotiate_interface(&connection, status_config.as_ref())
+                .await;
+            // A status query on the monitored interface follows the negotiated version
+            let status_config = status_config.as_mut().filter(|status_config| {
+                self.interface_candidates.contains(&status_config.interface)
+            });
+            if let Some(status_config) = status_config {
+                status_config.interface = interface.clone();
+            }
+            self.config.interface = Some(interface);
+        }
+        let percentage_config = match self.config.parse_percentage_status() {
+            Ok(config) => config,
+            Err(e) => {
+                debug!(
+                    "error: Failed to parse percentage status configuration: {}",
+                    e
+                );
+                return Err(error_not_found!("Invalid percentage status format: {}", e));
+            }
+        };
 
-                    Ok::<_, AppError>(value)
-                },
-                "initial property query",
-                RetryConfig::default(),
-            )
-            .await;
+        if let Some(percentage_config) = &percentage_config {
+            self.refresh_percentage(&connection, percentage_config)
+                .await;
+        }
 
-            // Handle the result after retries
-            match initial_state_result {
-                Ok(value) => {
-                    // Process and print the value (stdout flushing is handled internally)
-                    self.config.type_handler.process_and_print(&value);
-                }
-                Err(e) => {
-                    // If it's a service unavailable error after all retries, exit with proper error code
-                    if matches!(e, AppError::ServiceUnavailable(_, _)) {
-                        return Err(e);
+        let emit_signal_config = match self.config.parse_emit_signal() {
+            Ok(config) => config,
+            Err(e) => return Err(error_not_found!("Invalid signal format: {}", e)),
+        };
+
+        // Subscribe to owner changes before the first query, so no change goes unnoticed
+        let mut owner_stream = match self.config.watched_service() {
+            Ok(Some(service)) => Some(self.create_owner_stream(&connection, &service).await?),
+            Ok(None) => None,
+            Err(e) => return Err(error_not_found!("{}", e)),
+        };
+
+        let mut stream = match (&status_config, self.config.status_defer) {
+            // Deferred status query: subscribe first and only query if the service stays quiet
+            (Some(status_config), Some(defer_ms)) => {
+                let mut stream = self.create_message_stream(&connection).await?;
+
+                debug!(
+                    "Waiting up to {}ms for a value before querying initial state",
+                    defer_ms
+                );
+                // Messages without a monitored value, e.g. for other properties, don't count
+                let deadline = Instant::now() + Duration::from_millis(defer_ms);
+                let mut emitted = false;
+                while !emitted {
+                    match timer::timeout_at(deadline, stream.next()).await {
+                        Some(Some(msg)) => {
+                            emitted = self
+                                .handle_stream_item(msg, &connection, percentage_config.as_ref())
+                                .await?;
+                        }
+                        Some(None) => return Ok(()),
+                        None => break,
                     }
-                    // For other errors, just log a warning rather than failing completely
-                    debug!(
-                        "warn: Could not get initial property '{}' after retries: {}",
-                        status_config.property, e
-                    );
                 }
+                if emitted {
+                    debug!("Value received before status query, skipping it");
+                } else {
+                    self.query_initial_state(&connection, status_config).await?;
+                }
+                stream
             }
-        }
+            // --- PHASE 1: Initial State Query ---
+            (Some(status_config), None) if self.config.status_before_match => {
+                self.query_initial_state(&connection, status_config).await?;
+                self.create_message_stream(&connection).await?
+            }
+            // Subscribing first means a change between query and subscription can't be missed;
+            // signals arriving meanwhile are buffered and handled after the initial value
+            (Some(status_config), None) => {
+                let stream = self.create_message_stream(&connection).await?;
+                self.query_initial_state(&connection, status_config).await?;
+                stream
+            }
+            (None, _) => {
+                let stream = self.create_message_stream(&connection).await?;
+                if let Some(name) = &self.config.name_owner {
+                    self.query_name_owner(&connection, name).await?;
+                }
+                stream
+            }
+        };
 
-        // --- PHASE 2: Signal Listening ---
-        let mut stream = retry_operation(
-            || async { self.setup_message_stream(&connection).await },
-            "D-Bus message stream setup",
-        )
-        .await?;
+        self.emit_signals(&connection, emit_signal_config.as_ref())
+            .await;
 
+        // --- PHASE 2: Signal Listening ---
         debug!("Listening for D-Bus signals...");
 
+        let mut percentage_interval =
+            Ticker::every(self.config.percentage_interval.map(Duration::from_secs));
+        let mut config_watch =
+            Ticker::every_after(self.config.watch_file.then_some(CONFIG_WATCH_INTERVAL));
+        let mut config_modified = self.config_file_modified();
+
+        // The age is shown right away, so the first refresh is due one period later
+        let mut age_refresh = Ticker::every_after(self.config.show_age.map(Duration::from_secs));
+
+        // The first poll is due one period after the initial query
+        let mut poll_backup = Ticker::every_after(self.config.poll_backup.map(Duration::from_secs));
+
         // Main listening loop - now we only receive messages that match our criteria
-        while let Some(msg) = stream.next().await {
-            match msg {
-                Ok(message) => {
-                    if let Err(e) = self.process_message(&message) {
-                        // Print error code to stdout for waybar and log error
-                        report_error!(e, "Error processing message");
-                        // Continue listening rather than crashing on a single message error
+        loop {
+            tokio::select! {
+                msg = stream.next() => match msg {
+                    Some(msg) => {
+                        self.handle_stream_item(msg, &connection, percentage_config.as_ref())
+                            .await?;
+                    }
+                    None => break,
+                },
+                Some(msg) = next_message(&mut owner_stream) => {
+                    self.handle_owner_change(msg, &connection, status_config.as_ref())
+                        .await?
+                }
+                _ = config_watch.tick() => {
+                    let modified = self.config_file_modified();
+                    if modified != config_modified {
+                        config_modified = modified;
+                        if self.reload_config_file() {
+                            stream = self.create_message_stream(&connection).await?;
+                        }
+                    }
+                }
+                _ = age_refresh.tick() => self.reemit_last_record().await,
+                _ = timer::sleep_until(self.clear_deadline.get()) => self.clear_output(),
+                _ = timer::sleep_until(self.stale_deadline()) => {
+                    debug!("No value for {:?}s, marking it stale", self.config.stale_after);
+                    self.stale.set(true);
+                    self.reemit_last_record().await;
+                }
+                _ = poll_backup.tick() => {
+                    if let Some(status_config) = &status_config {
+                        self.poll_property(&connection, status_config).await;
+                    }
+                }
+                _ = percentage_interval.tick() => {
+                    if let Some(percentage_config) = &percentage_config {
+                        self.refresh_percentage(&connection, percentage_config).await;
+                        self.reemit_last_record().await;
                     }
                 }
+            }
+
+            self.emit_signals(&connection, emit_signal_config.as_ref())
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Pick the first interface candidate that the service provides
+    ///
+    /// Subscribing succeeds for any interface name, so the candidates are
+    /// checked by introspecting the --status object, or the --path object of
+    /// the service named like the candidate. Without a match, --interface is
+    /// used and fails as it would without fallbacks.
+    async fn negotiate_interface(
+        &self,
+        connection: &Connection,
+        status_config: Option<&StatusConfig>,
+    ) -> String {
+        for interface in &self.interface_candidates {
+            let (service, path) = match status_config {
+                Some(status_config) => (
+                    status_config.service.as_str(),
+                    status_config.object_path.as_str(),
+                ),
+                None => (
+                    interface.as_str(),
+                    self.config.path.as_deref().unwrap_or("/"),
+                ),
+            };
+
+            match provides_interface(connection, service, path, interface).await {
+                Ok(true) => {
+                    debug!("Using interface {}", interface);
+                    return interface.clone();
+                }
+                Ok(false) => debug!("{} {} does not provide {}", service, path, interface),
+                Err(e) => debug!("warn: Could not introspect {} {}: {}", service, path, e),
+            }
+        }
+
+        debug!("warn: No interface candidate is available, using the first");
+        self.interface_candidates[0].clone()
+    }
+
+    /// Modification time of the config file, if it can be read
+    fn config_file_modified(&self) -> Option<SystemTime> {
+        let path = self.config.config.as_ref()?;
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Apply a changed config file, returning whether the subscription must be renewed
+    ///
+    /// An invalid file, e.g. a partially written one, keeps the previous config.
+    fn reload_config_file(&mut self) -> bool {
+        match self.config.reload_config_file() {
+            Ok(monitors_changed) => {
+                debug!("Config file reloaded: {:?}", self.config.monitors());
+                // Handlers may have changed, so their state starts over
+                self.states.get_mut().clear();
+                monitors_changed
+            }
+            Err(e) => {
+                debug!("error: Keeping the previous config: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Subscribe to ownership changes of a well-known bus name
+    async fn create_owner_stream(
+        &self,
+        connection: &Connection,
+        service: &str,
+    ) -> Result<MessageStream, AppError> {
+        let match_rule = name_owner_rule(service)?;
+
+        debug!("Watching owner of {}: {}", service, match_rule);
+
+        retry_operation(
+            || async {
+                MessageStream::for_match_rule(match_rule.clone(), connection, None)
+                    .await
+                    .map_err(AppError::from)
+            },
+            "service owner watch setup",
+        )
+        .await
+    }
+
+    /// React to the watched service vanishing or (re)appearing
+    async fn handle_owner_change(
+        &self,
+        msg: zbus::Result<zbus::Message>,
+        connection: &Connection,
+        status_config: Option<&StatusConfig>,
+    ) -> Result<(), AppError> {
+        let message = match msg {
+            Ok(message) => message,
+            // Receive errors are handled the same way as for the monitored messages
+            Err(e) => {
+                self.handle_stream_item(Err(e), connection, None).await?;
+                return Ok(());
+            }
+        };
+
+        let (name, _old_owner, new_owner) =
+            match message.body().deserialize::<(String, String, String)>() {
+                Ok(args) => args,
                 Err(e) => {
-                    let app_error = AppError::from(e);
-                    report_error!(app_error, "Error receiving message");
+                    debug!("warn: Invalid NameOwnerChanged signal: {}", e);
+                    return Ok(());
+                }
+            };
 
-                    // Only exit if this is a permanent connection error
-                    if matches!(app_error, AppError::BadGateway(_, _)) {
-                        return Err(app_error);
+        if new_owner.is_empty() {
+            debug!("Service {} vanished", name);
+            match &self.config.service_gone_output {
+                Some(text) => {
+                    if let Err(e) = output::emit_json(&serde_json::json!({ "text": text })) {
+                        debug!("error: {}", e);
                     }
-                    // Otherwise continue listening for new messages
+                }
+                None => {
+                    let error = error_service_unavailable!("Service {} has no owner", name);
+                    report_error!(error);
                 }
             }
+        } else {
+            debug!("Service {} appeared with owner {}", name, new_owner);
+            // --- PHASE 1 again: the new instance may be in a different state ---
+            let result = match status_config {
+                Some(status_config) => self.query_initial_state(connection, status_config).await,
+                None => Ok(()),
+            };
+            if let Err(e) = result {
+                report_error!(e, "Initial state query after service reappeared failed");
+            }
         }
 
         Ok(())
     }
 
-    /// Establish D-Bus connection with fallback from session to system bus
-    async fn establish_connection(&self) -> Result<Connection, AppError> {
-        // Try to connect to session bus first, fallback to system bus
-        match Connection::session().await {
-            Ok(conn) => {
-                debug!("Connected to session bus");
-                Ok(conn)
+    /// Fetch the --percentage-status property, keeping the previous value on failure
+    async fn refresh_percentage(&self, connection: &Connection, percentage_config: &StatusConfig) {
+        let result = async {
+            let proxy = Proxy::new(
+                connection,
+                percentage_config.service.as_str(),
+                percentage_config.object_path.as_str(),
+                percentage_config.interface.as_str(),
+            )
+            .await?;
+
+            proxy
+                .get_property::<OwnedValue>(&percentage_config.property)
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(value) => match percentage_from_value(&value) {
+                Some(percentage) => self.percentage.set(Some(percentage)),
+                None => debug!("warn: Percentage property is not numeric: {:?}", value),
+            },
+            Err(e) => debug!("warn: Could not refresh percentage: {}", e),
+        }
+    }
+
+    /// Emit a formatted record, adding the percentage if configured
+    async fn emit(&self, record: Option<Record>) -> Result<(), String> {
+        let Some(record) = record else {
+            return Ok(());
+        };
+        if !self.is_stable(&record) {
+            return Ok(());
+        }
+
+        let text = record.json.get("text").and_then(|text| text.as_str());
+        if let (Some(_), Some(text)) = (&self.config.emit_signal, text) {
+            self.pending_signals.borrow_mut().push(text.to_string());
+        }
+
+        if self.last_record.borrow().as_ref() != Some(&record) {
+            self.last_change.set(Some(Instant::now()));
+        }
+        self.last_update.set(Some(Instant::now()));
+        self.emitted_values.set(self.emitted_values.get() + 1);
+        self.stale.set(false);
+
+        self.last_record.replace(Some(record.clone()));
+        output::emit_value(self.decorate(record)).await
+    }
+
+    /// Count consecutive identical values and check whether --stable-count is reached
+    fn is_stable(&self, record: &Record) -> bool {
+        let Some(required) = self.config.stable_count else {
+            return true;
+        };
+
+        let mut candidate = self.stable_candidate.borrow_mut();
+        let count = match candidate.as_mut() {
+            Some((value, count)) if value == record => {
+                *count += 1;
+                *count
+            }
+            _ => {
+                *candidate = Some((record.clone(), 1));
+                1
             }
+        };
+
+        if count < required {
+            debug!("Value seen {}/{} times, not emitted yet", count, required);
+        }
+        count >= required
+    }
+
+    /// Send the texts of the values emitted since the last call as --emit-signal signals
+    ///
+    /// A failed signal is logged and dropped, stdout output is not affected.
+    async fn emit_signals(&self, connection: &Connection, target: Option<&EmitSignalConfig>) {
+        let Some(target) = target else {
+            return;
+        };
+
+        let texts = std::mem::take(&mut *self.pending_signals.borrow_mut());
+        for text in texts {
+            let result = connection
+                .emit_signal(
+                    None::<zbus::names::BusName<'_>>,
+                    "/",
+                    target.interface.as_ref(),
+                    target.member.as_ref(),
+                    &(text.as_str(),),
+                )
+                .await;
+            if let Err(e) = result {
+                debug!("warn: Failed to emit signal: {}", e);
+            }
+        }
+    }
+
+    /// Schedule the --cleared-output after the duration carried by a monitored message
+    ///
+    /// Every message replaces a pending clear, so the latest message's duration
+    /// counts from its arrival. Without a positive duration, the value stays.
+    fn schedule_clear(&self, message: &zbus::Message) {
+        let Some(index) = self.config.clear_after_from_arg else {
+            return;
+        };
+        let header = message.header();
+        let member = header.member().map(|m| m.as_str()).unwrap_or_default();
+        if !self.config.monitors().contains(&member) {
+            return;
+        }
+
+        let duration = match selector::argument(message, index) {
+            Ok(Some(value)) => number_from_value(&value),
+            Ok(None) => None,
             Err(e) => {
-                debug!("Failed to connect to session bus: {}", e);
-                debug!("Trying system bus");
+                debug!("warn: {}", e);
+                None
+            }
+        };
+        let deadline = match duration {
+            Some(ms) if ms > 0 => {
+                debug!("Clearing the output in {}ms", ms);
+                Some(Instant::now() + Duration::from_millis(ms as u64))
+            }
+            _ => {
+                debug!("No duration in argument {}, the value stays", index);
+                None
+            }
+        };
+        self.clear_deadline.set(deadline);
+    }
 
-                match Connection::system().await {
-                    Ok(conn) => {
-                        debug!("Connected to system bus");
-                        Ok(conn)
-                    }
-                    Err(system_err) => {
-                        debug!("error: Failed to connect to both session and system bus");
-                        debug!("error: Session bus error: {}", e);
-                        debug!("error: System bus error: {}", system_err);
-                        Err(AppError::connection_failed(system_err))
+    /// Emit the --cleared-output once the scheduled duration elapsed
+    fn clear_output(&self) {
+        debug!("Clearing the output");
+        self.clear_deadline.set(None);
+        // The cleared state must not be replaced by the old value, e.g. by --show-age
+        self.last_record.replace(None);
+
+        let text = &self.config.cleared_output;
+        if let Err(e) = output::emit_json(&serde_json::json!({ "text": text })) {
+            debug!("error: {}", e);
+        }
+    }
+
+    /// Emit the last record again, e.g. after the percentage changed
+    async fn reemit_last_record(&self) {
+        let last_record = self.last_record.borrow().clone();
+        let Some(record) = last_record else {
+            return;
+        };
+        if let Err(e) = output::emit_value(self.decorate(record)).await {
+            debug!("error: {}", e);
+        }
+    }
+
+    /// Add the listener's own information to a handler's record
+    fn decorate(&self, record: Record) -> Record {
+        self.with_stale_class(self.with_age(self.with_percentage(record)))
+    }
+
+    /// When the last value becomes stale with --stale-after, unless it already is
+    fn stale_deadline(&self) -> Option<Instant> {
+        let stale_after = Duration::from_secs(self.config.stale_after?);
+        if self.stale.get() {
+            return None;
+        }
+        self.last_update.get().map(|updated| updated + stale_after)
+    }
+
+    /// Flag a stale value with the --stale-class, keeping its text
+    fn with_stale_class(&self, mut record: Record) -> Record {
+        if self.stale.get() {
+            record.json["class"] = serde_json::json!(self.config.stale_class);
+        }
+        record
+    }
+
+    /// Format the text with the time since the value last changed, for --show-age
+    fn with_age(&self, mut record: Record) -> Record {
+        let (Some(_), Some(changed)) = (self.config.show_age, self.last_change.get()) else {
+            return record;
+        };
+
+        if let Some(text) = record.json.get("text").and_then(|text| text.as_str()) {
+            let text = self
+                .config
+                .age_format
+                .replace("{text}", text)
+                .replace("{age}", &format_age(changed.elapsed()));
+            record.json["text"] = serde_json::Value::String(text);
+        }
+        record
+    }
+
+    fn with_percentage(&self, mut record: Record) -> Record {
+        if let Some(percentage) = self.percentage.get() {
+            record.json["percentage"] = serde_json::json!(percentage);
+        }
+        record
+    }
+
+    /// Query the initial property value and print it
+    async fn query_initial_state(
+        &self,
+        connection: &Connection,
+        status_config: &StatusConfig,
+    ) -> Result<(), AppError> {
+        // Wrap the property query in retry for service availability
+        let initial_state_result = retry_operation_with_config(
+            || async {
+                let proxy = Proxy::new(
+                    connection,
+                    status_config.service.as_str(),
+                    status_config.object_path.as_str(),
+                    status_config.interface.as_str(),
+                )
+                .await?;
+
+                let value = proxy
+                    .get_property::<zbus::zvariant::Value>(&status_config.property)
+                    .await?;
+
+                Ok::<_, AppError>(value)
+            },
+            "initial property query",
+            RetryConfig::default(),
+        )
+        .await;
+
+        // Handle the result after retries
+        match initial_state_result {
+            Ok(value) => {
+                // Process and print the value (stdout flushing is handled internally)
+                let member = self.config.monitor();
+                self.remember_status_value(member, &value);
+                let result = match self.format_member_value(member, &value) {
+                    Ok(record) => self.emit(record).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    debug!("warn: {}", e);
+                }
+            }
+            Err(e) => {
+                // If it's a service unavailable error after all retries, exit with proper error code
+                if matches!(e, AppError::ServiceUnavailable(_, _)) {
+                    return Err(e);
+                }
+                // For other errors, just log a warning rather than failing completely
+                debug!(
+                    "warn: Could not get initial property '{}' after retries: {}",
+                    status_config.property, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Query whether the --name-owner name currently has an owner and print it
+    async fn query_name_owner(&self, connection: &Connection, name: &str) -> Result<(), AppError> {
+        let has_owner = retry_operation(
+            || async {
+                let reply = connection
+                    .call_method(
+                        Some("org.freedesktop.DBus"),
+                        "/org/freedesktop/DBus",
+                        Some("org.freedesktop.DBus"),
+                        "NameHasOwner",
+                        &name,
+                    )
+                    .await?;
+                Ok::<bool, AppError>(reply.body().deserialize()?)
+            },
+            "name owner query",
+        )
+        .await?;
+
+        if let Err(e) = self
+            .process_member_value(self.config.monitor(), &Value::from(has_owner))
+            .await
+        {
+            debug!("warn: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Poll the status property as a backup for missed PropertiesChanged signals
+    ///
+    /// The property is read without retries or caching, and the value is only
+    /// emitted if it differs from the last emitted one.
+    async fn poll_property(&self, connection: &Connection, status_config: &StatusConfig) {
+        let result = async {
+            let value = get_property(
+                connection,
+                &status_config.service,
+                &status_config.object_path,
+                &status_config.interface,
+                &status_config.property,
+            )
+            .await?;
+
+            // Compared before formatting, as e.g. --emit-delta formats the same value differently
+            let member = self.config.monitor();
+            let unchanged = self
+                .last_status_value
+                .borrow()
+                .as_ref()
+                .is_some_and(|last| {
+                    selector::unwrap_variant(last) == selector::unwrap_variant(&value)
+                });
+            if unchanged {
+                debug!("Polled value unchanged, not emitted");
+                return Ok(());
+            }
+            debug!("Polled value changed without a signal");
+            self.remember_status_value(member, &value);
+            let record = self.format_member_value(member, &value)?;
+            self.emit(record).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            debug!(
+                "warn: Backup poll of '{}' failed: {}",
+                status_config.property, e
+            );
+        }
+    }
+
+    /// Fetch the monitored properties a PropertiesChanged signal invalidated without a value
+    ///
+    /// The properties are read from the signal's sender and path. A failed read
+    /// is logged and skipped, like a signal without the property.
+    async fn fetch_invalidated_properties(&self, connection: &Connection, message: &zbus::Message) {
+        let Ok((interface, _, invalidated)) =
+            message
+                .body()
+                .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+        else {
+            // Malformed signals are already reported when processing the message
+            return;
+        };
+        if interface != self.config.interface() {
+            return;
+        }
+
+        let header = message.header();
+        let (Some(sender), Some(path)) = (header.sender(), header.path()) else {
+            debug!("warn: PropertiesChanged without sender or path, can't fetch properties");
+            return;
+        };
+
+        for member in self.config.monitors() {
+            if !invalidated.iter().any(|property| property == member) {
+                continue;
+            }
+
+            debug!("Property {} invalidated, fetching its value", member);
+            let result = match get_property(connection, sender, path, &interface, member).await {
+                Ok(value) => self.process_member_value(member, &value).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                debug!(
+                    "warn: Could not fetch invalidated property '{}': {}",
+                    member, e
+                );
+            }
+        }
+    }
+
+    /// Set up the message stream, retrying on failure
+    async fn create_message_stream(
+        &self,
+        connection: &Connection,
+    ) -> Result<MessageStream, AppError> {
+        retry_operation(
+            || async { self.setup_message_stream(connection).await },
+            "D-Bus message stream setup",
+        )
+        .await
+    }
+
+    /// Handle a single item received from the message stream
+    ///
+    /// Returns whether a value was emitted for it, and an error only if the
+    /// connection failed permanently.
+    async fn handle_stream_item(
+        &self,
+        msg: zbus::Result<zbus::Message>,
+        connection: &Connection,
+        percentage_config: Option<&StatusConfig>,
+    ) -> Result<bool, AppError> {
+        let emitted_values = self.emitted_values.get();
+        match msg {
+            Ok(message) => {
+                recording::record(&mut self.recorder.borrow_mut(), &message);
+
+                if let Some(percentage_config) = percentage_config {
+                    self.refresh_percentage(connection, percentage_config).await;
+                }
+
+                if let Err(e) = self.process_message(&message).await {
+                    // Print error code to stdout for waybar and log error
+                    e.print_error_code();
+                    self.log_throttled(format!("error: Error processing message: {}", e));
+                    // Continue listening rather than crashing on a single message error
+                }
+                self.schedule_clear(&message);
+
+                if self.config.fetch_invalidated {
+                    self.fetch_invalidated_properties(connection, &message)
+                        .await;
+                }
+                Ok(self.emitted_values.get() != emitted_values)
+            }
+            Err(e) => {
+                let app_error = AppError::from(e);
+                app_error.print_error_code();
+                self.log_throttled(format!("error: Error receiving message: {}", app_error));
+
+                // Only exit if this is a permanent connection error
+                if matches!(app_error, AppError::BadGateway(_, _)) {
+                    return Err(app_error);
+                }
+                // Otherwise continue listening for new messages
+                Ok(false)
+            }
+        }
+    }
+
+    /// Build the match rule for the monitored signal
+    fn build_match_rule(&self) -> Result<MatchRule<'_>, AppError> {
+        if let Some(name) = &self.config.name_owner {
+            return name_owner_rule(name);
+        }
+
+        let interface = self.config.interface();
+        let monitors = self.config.monitors();
+
+        let mut builder = MatchRule::builder().msg_type(self.config.msg_type.into());
+
+        builder = if self.config.properties_changed {
+            // The monitored property is reported through the generic Properties interface,
+            // with the property's interface as first argument
+            builder
+                .interface(PROPERTIES_INTERFACE)
+                .map_err(|e| {
+                    error_not_found!("Invalid interface '{}': {}", PROPERTIES_INTERFACE, e)
+                })?
+                .member("PropertiesChanged")
+                .map_err(|e| error_not_found!("Invalid monitor 'PropertiesChanged': {}", e))?
+                .arg(0, interface)
+                .map_err(|e| error_not_found!("Invalid interface '{}': {}", interface, e))?
+        } else {
+            builder = builder
+                .interface(interface)
+                .map_err(|e| error_not_found!("Invalid interface '{}': {}", interface, e))?;
+
+            // A rule holds a single member, so several members are filtered on our side
+            match monitors.as_slice() {
+                [monitor] => builder
+                    .member(*monitor)
+                    .map_err(|e| error_not_found!("Invalid monitor '{}': {}", monitor, e))?,
+                _ => {
+                    for monitor in &monitors {
+                        zbus::names::MemberName::try_from(*monitor).map_err(|e| {
+                            error_not_found!("Invalid monitor '{}': {}", monitor, e)
+                        })?;
                     }
+                    builder
                 }
             }
+        };
+
+        if let Some(path) = &self.config.path {
+            builder = builder
+                .path(path.as_str())
+                .map_err(|e| error_not_found!("Invalid path '{}': {}", path, e))?;
         }
+
+        Ok(builder.build())
     }
 
     /// Setup message stream for the specific signal
@@ -144,38 +1062,247 @@ impl DBusListener {
         connection: &Connection,
     ) -> Result<MessageStream, AppError> {
         // Create a match rule for the specific signal
-        let match_rule: MatchRule<'_> = MatchRule::builder()
-            .msg_type(zbus::message::Type::Signal)
-            .interface(self.config.interface.as_str())
-            .map_err(|e| error_not_found!("Invalid interface '{}': {}", self.config.interface, e))?
-            .member(self.config.monitor.as_str())
-            .map_err(|e| error_not_found!("Invalid monitor '{}': {}", self.config.monitor, e))?
-            .build();
+        let match_rule = self.build_match_rule()?;
 
-        debug!(
-            "Adding match rule for interface: {}, monitor: {}",
-            self.config.interface, self.config.monitor
-        );
+        debug!("Adding match rule: {}", match_rule);
+
+        // Method calls are only delivered to their destination, so eavesdrop on them
+        // with a bus monitor. A monitor can't send messages anymore, so it gets its
+        // own connection and the main one stays usable for queries and signals.
+        if self.config.msg_type == MessageType::MethodCall {
+            let monitor_rule = match_rule.to_string();
+            let monitor_connection = establish_connection(self.config.bus).await?;
+            let stream = MessageStream::for_match_rule(match_rule, &monitor_connection, None)
+                .await
+                .map_err(AppError::from)?;
+            self.become_monitor(&monitor_connection, monitor_rule)
+                .await?;
+            return Ok(stream);
+        }
 
         // Create a filtered message stream for our match rule
         // This automatically registers the rule with the bus
-        let stream = MessageStream::for_match_rule(match_rule, connection, None)
+        MessageStream::for_match_rule(match_rule, connection, None)
+            .await
+            .map_err(AppError::from)
+    }
+
+    /// Turn the connection into a monitor receiving all messages matching the rule
+    ///
+    /// A monitor connection can't send messages anymore, so this has to be the
+    /// last call made on the connection.
+    async fn become_monitor(&self, connection: &Connection, rule: String) -> Result<(), AppError> {
+        debug!("Becoming a bus monitor for rule: {}", rule);
+
+        connection
+            .call_method(
+                Some("org.freedesktop.DBus"),
+                "/org/freedesktop/DBus",
+                Some("org.freedesktop.DBus.Monitoring"),
+                "BecomeMonitor",
+                &(vec![rule], 0u32),
+            )
             .await
             .map_err(AppError::from)?;
 
-        Ok(stream)
+        Ok(())
+    }
+
+    /// Extract the changed properties from a PropertiesChanged signal for our interface
+    fn extract_changed_properties(
+        &self,
+        message: &zbus::Message,
+    ) -> Result<Option<HashMap<String, OwnedValue>>, String> {
+        let (interface, changed, _invalidated) = message
+            .body()
+            .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+            .map_err(|e| format!("Failed to deserialize PropertiesChanged: {}", e))?;
+
+        if interface != self.config.interface() {
+            debug!("Ignoring PropertiesChanged for interface {}", interface);
+            return Ok(None);
+        }
+
+        Ok(Some(changed))
+    }
+
+    /// Select the monitored value and run the member's type handler on it
+    async fn process_member_value(&self, member: &str, value: &Value<'_>) -> Result<(), String> {
+        self.remember_status_value(member, value);
+        let key_path = self.config.key_path();
+        let Some(selected) = selector::select_keys(value, &key_path)? else {
+            debug!("Key path {:?} not found in message, skipping", key_path);
+            return Ok(());
+        };
+        let selected = match &self.config.array_struct_key {
+            Some(key) => {
+                let field = self.config.array_struct_field;
+                match selector::select_array_struct(selected, key, field)? {
+                    Some(selected) => selected,
+                    None => {
+                        debug!("No struct with key '{}' in message, skipping", key);
+                        return Ok(());
+                    }
+                }
+            }
+            None => selected,
+        };
+        let selected = selector::select_struct_fields(selected, &self.config.struct_field)?;
+
+        let record = self.format_member_value(member, selected)?;
+        self.emit(record).await
+    }
+
+    /// Keep the raw value of the --status property, to recognize unchanged values when polling
+    fn remember_status_value(&self, member: &str, value: &Value) {
+        if self.config.poll_backup.is_none() || member != self.config.monitor() {
+            return;
+        }
+        self.last_status_value.replace(value.try_to_owned().ok());
+    }
+
+    /// Format a member's value with its type handler and handler state
+    fn format_member_value(&self, member: &str, value: &Value) -> Result<Option<Record>, String> {
+        let record = self.config.handler_for(member).format_value(
+            value,
+            self.states
+                .borrow_mut()
+                .entry(member.to_string())
+                .or_default(),
+        )?;
+        Ok(self.count_true(record))
+    }
+
+    /// Replace the record of a boolean member by the number of true members, for --count-true
+    ///
+    /// Members that haven't reported a value yet count as false, so the count is
+    /// emitted from the first value on; the tooltip tells how many are unknown.
+    fn count_true(&self, record: Option<Record>) -> Option<Record> {
+        if !self.config.count_true || record.is_none() {
+            return record;
+        }
+
+        let states = self.states.borrow();
+        let values: Vec<Option<bool>> = self
+            .config
+            .monitors()
+            .into_iter()
+            .map(|member| states.get(member).and_then(|state| state.last_boolean))
+            .collect();
+        let count = values.iter().filter(|value| **value == Some(true)).count();
+        let unknown = values.iter().filter(|value| value.is_none()).count();
+
+        let mut tooltip = format!("{} of {} true", count, values.len());
+        if unknown > 0 {
+            tooltip.push_str(&format!(", {} unknown", unknown));
+        }
+        let tone = if count > 0 {
+            Tone::Positive
+        } else {
+            Tone::Negative
+        };
+        Some(
+            Record::new(serde_json::json!({ "text": count.to_string(), "tooltip": tooltip }))
+                .with_tone(tone),
+        )
+    }
+
+    /// Route a message to the type handlers of the members it carries
+    async fn dispatch_message(&self, message: &zbus::Message) -> Result<(), String> {
+        if self.config.name_owner.is_some() {
+            let (_name, _old_owner, new_owner) = message
+                .body()
+                .deserialize::<(String, String, String)>()
+                .map_err(|e| format!("Failed to deserialize NameOwnerChanged: {}", e))?;
+            let has_owner = !new_owner.is_empty();
+            return self
+                .process_member_value(self.config.monitor(), &Value::from(has_owner))
+                .await;
+        }
+
+        let monitors = self.config.monitors();
+
+        if self.config.properties_changed {
+            let Some(changed) = self.extract_changed_properties(message)? else {
+                return Ok(());
+            };
+
+            let mut found = false;
+            for member in monitors {
+                if let Some(value) = changed.get(member) {
+                    found = true;
+                    self.process_member_value(member, value).await?;
+                }
+            }
+            if !found {
+                debug!("Monitored property not among the changed properties, skipping");
+            }
+            return Ok(());
+        }
+
+        let header = message.header();
+        let Some(member) = header.member().map(|m| m.as_str()) else {
+            debug!("Ignoring message without member");
+            return Ok(());
+        };
+        if !monitors.contains(&member) {
+            debug!("Ignoring unmonitored member {}", member);
+            return Ok(());
+        }
+
+        if !self.config.has_selector() {
+            // Use the new unified format_message method from TypeHandler
+            let record = self.config.handler_for(member).format_message(
+                message,
+                self.states
+                    .borrow_mut()
+                    .entry(member.to_string())
+                    .or_default(),
+            )?;
+            self.emit(self.count_true(record)).await
+        } else if self.config.dict_key.is_none() && self.config.array_struct_key.is_none() {
+            // Only --struct-field selects, starting with the argument
+            let value = selector::all_arguments(message)?;
+            self.process_member_value(member, &value).await
+        } else {
+            let value = selector::first_argument(message)?;
+            self.process_member_value(member, &value).await
+        }
+    }
+
+    /// Log an error about a received message, subject to --log-throttle
+    fn log_throttled(&self, message: String) {
+        self.log_throttle.borrow_mut().debug(message);
     }
 
     /// Process a single D-Bus message and print the result
-    fn process_message(&self, message: &zbus::Message) -> Result<(), AppError> {
+    async fn process_message(&self, message: &zbus::Message) -> Result<(), AppError> {
         let body = message.body();
         debug!("Processing message with signature: {:?}", body.signature());
 
-        // Use the new unified process_message method from TypeHandler
-        match self.config.type_handler.process_message(message) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                debug!("error: {}", e);
+        let Err(e) = self.dispatch_message(message).await else {
+            return Ok(());
+        };
+
+        match self.config.on_unexpected {
+            UnexpectedPolicy::Skip => {
+                self.log_throttled(format!("Skipping message: {}", e));
+                Ok(())
+            }
+            UnexpectedPolicy::Raw => {
+                self.log_throttled(format!("Emitting raw value: {}", e));
+                // Like any value, so e.g. --max-length and --stable-count apply
+                let result = match selector::first_argument(message) {
+                    Ok(value) => {
+                        let json = serde_json::json!({ "text": format!("{:?}", *value) });
+                        self.emit(Some(Record::new(json))).await
+                    }
+                    Err(e) => Err(e),
+                };
+                result.map_err(|e| error_message_processing!("{}", e))
+            }
+            UnexpectedPolicy::Error => {
+                self.log_throttled(format!("error: {}", e));
                 Err(error_message_processing!(
                     "Failed to process message with signature: {:?}: {}",
                     body.signature(),
@@ -185,3 +1312,294 @@ impl DBusListener {
         }
     }
 }
+
+/// Establish D-Bus connection on the given bus
+pub async fn establish_connection(bus: BusType) -> Result<Connection, AppError> {
+    match bus {
+        BusType::Session => {
+            let conn = Connection::session()
+                .await
+                .map_err(|e| bus_connection_error(BusType::Session, e))?;
+            debug!("Connected to session bus");
+            Ok(conn)
+        }
+        BusType::System => {
+            let conn = Connection::system()
+                .await
+                .map_err(|e| bus_connection_error(BusType::System, e))?;
+            debug!("Connected to system bus");
+            Ok(conn)
+        }
+        BusType::Auto => establish_fallback_connection().await,
+    }
+}
+
+/// Establish D-Bus connection with fallback from session to system bus
+async fn establish_fallback_connection() -> Result<Connection, AppError> {
+    // Try to connect to session bus first, fallback to system bus
+    match Connection::session().await {
+        Ok(conn) => {
+            debug!("Connected to session bus");
+            Ok(conn)
+        }
+        Err(e) => {
+            debug!("Failed to connect to session bus: {}", e);
+            debug!("Trying system bus");
+
+            match Connection::system().await {
+                Ok(conn) => {
+                    debug!("Connected to system bus");
+                    Ok(conn)
+                }
+                Err(system_err) => {
+                    debug!("error: Failed to connect to both session and system bus");
+                    debug!("error: Session bus error: {}", e);
+                    debug!("error: System bus error: {}", system_err);
+                    // A malformed session bus address is what needs fixing, whatever the system bus did
+                    if matches!(e, zbus::Error::Address(_)) {
+                        return Err(bus_connection_error(BusType::Session, e));
+                    }
+                    if AppError::is_missing_bus(&e) && AppError::is_missing_bus(&system_err) {
+                        return Err(AppError::no_bus(
+                            "No D-Bus available: neither a session nor a system bus was \
+                             found (running outside a desktop session?)",
+                            system_err,
+                        ));
+                    }
+                    Err(bus_connection_error(BusType::System, system_err))
+                }
+            }
+        }
+    }
+}
+
+/// Describe a failed connection to a bus, explaining where the bus was expected if it's missing
+///
+/// A malformed bus address is a configuration error rather than a missing bus.
+fn bus_connection_error(bus: BusType, err: zbus::Error) -> AppError {
+    let (name, variable) = match bus {
+        BusType::System => ("system", "DBUS_SYSTEM_BUS_ADDRESS"),
+        _ => ("session", "DBUS_SESSION_BUS_ADDRESS"),
+    };
+    if let zbus::Error::Address(reason) = &err {
+        return error_not_found!("Invalid {} bus address in {}: {}", name, variable, reason);
+    }
+    if !AppError::is_missing_bus(&err) {
+        return AppError::connection_failed(err);
+    }
+
+    let location = match std::env::var(variable) {
+        Ok(address) => format!("{} is {}", variable, address),
+        Err(_) if bus == BusType::System => {
+            format!("{} is not set and the default socket is missing", variable)
+        }
+        Err(_) => format!(
+            "{} is not set and $XDG_RUNTIME_DIR/bus is missing (running outside a desktop session?)",
+            variable
+        ),
+    };
+
+    AppError::no_bus(format!("No {} bus found: {}", name, location), err)
+}
+
+/// Match rule for ownership changes of a well-known bus name
+fn name_owner_rule(name: &str) -> Result<MatchRule<'_>, AppError> {
+    Ok(MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender("org.freedesktop.DBus")
+        .and_then(|builder| builder.interface("org.freedesktop.DBus"))
+        .and_then(|builder| builder.member("NameOwnerChanged"))
+        .and_then(|builder| builder.arg(0, name))
+        .map_err(|e| error_not_found!("Invalid service name '{}': {}", name, e))?
+        .build())
+}
+
+/// Whether an object implements an interface, according to its introspection XML
+pub async fn provides_interface(
+    connection: &Connection,
+    service: &str,
+    path: &str,
+    interface: &str,
+) -> zbus::Result<bool> {
+    let reply = connection
+        .call_method(
+            Some(service),
+            path,
+            Some("org.freedesktop.DBus.Introspectable"),
+            "Introspect",
+            &(),
+        )
+        .await?;
+    let xml: String = reply.body().deserialize()?;
+    Ok(xml.contains(&format!("<interface name=\"{}\"", interface)))
+}
+
+/// Read a property with a plain Properties.Get call, bypassing any cache
+async fn get_property(
+    connection: &Connection,
+    service: &str,
+    path: &str,
+    interface: &str,
+    property: &str,
+) -> Result<OwnedValue, String> {
+    let reply = connection
+        .call_method(
+            Some(service),
+            path,
+            Some(PROPERTIES_INTERFACE),
+            "Get",
+            &(interface, property),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    reply
+        .body()
+        .deserialize::<OwnedValue>()
+        .map_err(|e| e.to_string())
+}
+
+/// Wait for the next item of an optional message stream, forever if there is none
+async fn next_message(stream: &mut Option<MessageStream>) -> Option<zbus::Result<zbus::Message>> {
+    match stream {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Format a duration as a short relative age, e.g. "42s" or "3h"
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        seconds @ 0..60 => format!("{}s", seconds),
+        seconds @ 60..3600 => format!("{}m", seconds / 60),
+        seconds @ 3600..86400 => format!("{}h", seconds / 3600),
+        seconds => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Interpret a numeric property as a percentage between 0 and 100
+fn percentage_from_value(value: &Value) -> Option<i64> {
+    number_from_value(value).map(|percentage| percentage.clamp(0, 100))
+}
+
+/// Read any integer or float value, rounding floats
+fn number_from_value(value: &Value) -> Option<i64> {
+    let number = match selector::unwrap_variant(value) {
+        Value::U8(n) => i64::from(*n),
+        Value::I16(n) => i64::from(*n),
+        Value::U16(n) => i64::from(*n),
+        Value::I32(n) => i64::from(*n),
+        Value::U32(n) => i64::from(*n),
+        Value::I64(n) => *n,
+        Value::U64(n) => i64::try_from(*n).ok()?,
+        Value::F64(n) => n.round() as i64,
+        _ => return None,
+    };
+    Some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use clap::Parser;
+
+    fn listener(args: &[&str]) -> DBusListener {
+        let args = test_support::argv(&[args, &["string"]].concat());
+        let mut config = Config::try_parse_from(args).unwrap();
+        config.resolve().unwrap();
+        config.validate().unwrap();
+        DBusListener::new(config, None)
+    }
+
+    /// Whether each of the values in turn would be emitted
+    fn stable(listener: &DBusListener, texts: &[&str]) -> Vec<bool> {
+        texts
+            .iter()
+            .map(|text| listener.is_stable(&Record::new(serde_json::json!({ "text": text }))))
+            .collect()
+    }
+
+    #[test]
+    fn stable_count_suppresses_alternating_values() {
+        let listener = listener(&["--stable-count", "2"]);
+        let emitted = stable(&listener, &["on", "off", "on", "off", "on"]);
+        assert_eq!(emitted, [false; 5]);
+    }
+
+    #[test]
+    fn stable_count_emits_repeated_values() {
+        let listener = listener(&["--stable-count", "3"]);
+        let emitted = stable(&listener, &["on", "on", "on", "on"]);
+        assert_eq!(emitted, [false, false, true, true]);
+    }
+
+    #[test]
+    fn stable_count_restarts_on_a_change() {
+        let listener = listener(&["--stable-count", "2"]);
+        let emitted = stable(&listener, &["on", "on", "off", "on", "on", "off", "off"]);
+        assert_eq!(emitted, [false, true, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn every_value_is_stable_without_stable_count() {
+        let listener = listener(&[]);
+        let emitted = stable(&listener, &["on", "off", "on"]);
+        assert_eq!(emitted, [true; 3]);
+    }
+
+    /// Set in the child process of [`missing_buses_are_reported_as_such`]
+
+    #[tokio::test]
+    async fn missing_buses_are_reported_as_such() {
+        if !test_support::in_child() {
+            // Bus addresses come from the environment, so connect in a child process without any
+            let output = test_support::child_test(
+                "dbus_listener::tests::missing_buses_are_reported_as_such",
+            )
+            .env_remove("DBUS_SESSION_BUS_ADDRESS")
+            .env(
+                "DBUS_SYSTEM_BUS_ADDRESS",
+                "unix:path=/nonexistent/system_bus_socket",
+            )
+            .env("XDG_RUNTIME_DIR", "/nonexistent")
+            .output()
+            .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            return;
+        }
+
+        let error = establish_connection(BusType::Session).await.unwrap_err();
+        assert_eq!(error.code(), 502);
+        assert!(
+            error.to_string().contains("No session bus found"),
+            "{}",
+            error
+        );
+
+        let error = establish_connection(BusType::System).await.unwrap_err();
+        let expected = "No system bus found: DBUS_SYSTEM_BUS_ADDRESS is unix:path=/nonexistent";
+        assert!(error.to_string().contains(expected), "{}", error);
+
+        let error = establish_connection(BusType::Auto).await.unwrap_err();
+        assert!(
+            error.to_string().contains("No D-Bus available"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn invalid_bus_addresses_are_configuration_errors() {
+        let error = bus_connection_error(BusType::Session, zbus::Error::Address("bad".into()));
+        assert_eq!(error.code(), 404);
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid session bus address in DBUS_SESSION_BUS_ADDRESS"),
+            "{}",
+            error
+        );
+    }
+}
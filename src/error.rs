@@ -54,14 +54,7 @@ impl AppError {
 
     /// Print the error code to stdout for waybar in JSON format
     pub fn print_error_code(&self) {
-        // The error code becomes the text, the error message becomes the tooltip
-        let error_code = self.format_for_waybar();
-        let error_message = self.to_string();
-        println!(
-            "{{\"text\": \"{}\", \"tooltip\": \"{}\"}}",
-            error_code, error_message
-        );
-        // No need to flush here as println! automatically flushes
+        crate::output::emit_error(self);
     }
 
     /// Create a connection error
@@ -72,6 +65,25 @@ impl AppError {
         )
     }
 
+    /// Create a connection error for a bus that doesn't exist
+    ///
+    /// Keeps the E502 code, but explains the environment problem instead of
+    /// showing a bare I/O error.
+    pub fn no_bus(msg: impl Into<String>, err: zbus::Error) -> Self {
+        Self::BadGateway(msg.into(), Some(Box::new(err)))
+    }
+
+    /// Whether a connection error means that there is no bus to connect to
+    pub fn is_missing_bus(err: &zbus::Error) -> bool {
+        match err {
+            zbus::Error::InputOutput(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            ),
+            _ => false,
+        }
+    }
+
     /// Create a service unavailable error
     pub fn service_unavailable(msg: impl Into<String>) -> Self {
         Self::ServiceUnavailable(msg.into(), None)
@@ -167,3 +179,46 @@ impl From<zbus::Error> for AppError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(address: &str) -> zbus::Error {
+        let builder = zbus::connection::Builder::address(address);
+        match builder {
+            Ok(builder) => builder.build().await.unwrap_err(),
+            Err(e) => e,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_missing_socket_is_a_missing_bus() {
+        let err = connect("unix:path=/nonexistent/bus").await;
+        assert!(AppError::is_missing_bus(&err), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_address_is_not_a_missing_bus() {
+        let err = connect("not-an-address").await;
+        assert!(matches!(err, zbus::Error::Address(_)), "{:?}", err);
+        assert!(!AppError::is_missing_bus(&err));
+    }
+
+    #[test]
+    fn other_errors_are_not_a_missing_bus() {
+        let err = zbus::Error::Failure("Authentication failed".to_string());
+        assert!(!AppError::is_missing_bus(&err));
+        let err = zbus::Error::InputOutput(std::sync::Arc::new(std::io::Error::from(
+            std::io::ErrorKind::PermissionDenied,
+        )));
+        assert!(!AppError::is_missing_bus(&err));
+    }
+
+    #[test]
+    fn missing_buses_keep_the_connection_error_code() {
+        let err = AppError::no_bus("No session bus found", zbus::Error::Unsupported);
+        assert_eq!(err.code(), 502);
+        assert!(!err.is_permanent());
+    }
+}
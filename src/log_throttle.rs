@@ -0,0 +1,62 @@
+/// Rate limiting of repeated log messages
+///
+/// A misshapen signal that fires continuously would otherwise log the same
+/// error for every message. Each distinct message is logged at most once per
+/// interval; once the interval has passed, the number of dropped repetitions
+/// is logged with the next message.
+use log::debug;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// When a message was last logged and how often it was dropped since
+struct Seen {
+    logged: Instant,
+    suppressed: usize,
+}
+
+/// Debug logger dropping identical messages within an interval
+pub struct LogThrottle {
+    interval: Option<Duration>,
+    seen: HashMap<String, Seen>,
+}
+
+impl LogThrottle {
+    /// Throttle to one message per `interval`, or log everything without an interval
+    pub fn new(interval: Option<Duration>) -> Self {
+        LogThrottle {
+            interval,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Log a message, unless the same message was logged within the interval
+    pub fn debug(&mut self, message: String) {
+        let Some(interval) = self.interval else {
+            debug!("{}", message);
+            return;
+        };
+
+        // Forget messages that stopped repeating, reporting their dropped repetitions
+        self.seen.retain(|message, seen| {
+            let expired = seen.logged.elapsed() >= interval;
+            if expired && seen.suppressed > 0 {
+                debug!("{} (repeated {} times)", message, seen.suppressed);
+            }
+            !expired
+        });
+
+        match self.seen.get_mut(&message) {
+            Some(seen) => seen.suppressed += 1,
+            None => {
+                debug!("{}", message);
+                self.seen.insert(
+                    message,
+                    Seen {
+                        logged: Instant::now(),
+                        suppressed: 0,
+                    },
+                );
+            }
+        }
+    }
+}
@@ -1,30 +1,113 @@
 mod cli;
+mod config_file;
 mod dbus_listener;
 mod error;
+mod log_throttle;
+mod mapping;
+mod output;
+mod preset;
+mod properties;
+mod recording;
 mod retry;
+mod selector;
+mod selftest;
+#[cfg(test)]
+mod test_support;
+mod timer;
+mod transform;
 
 use clap::Parser;
 use dbus_listener::DBusListener;
 use error::AppError;
 use log::debug;
+use retry::RetryConfig;
+use std::time::Duration;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), AppError> {
     // Initialize logger
     env_logger::init();
 
-    let config = cli::Config::parse();
+    // The self-test and the property listing take no monitoring options, which are
+    // required for the handler subcommands, so they bypass the regular parser. They
+    // are listed in --help by cli::OTHER_COMMANDS.
+    match std::env::args().nth(1).as_deref() {
+        Some("selftest") => output::exit(selftest::run().await),
+        Some("properties") => output::exit(properties::run().await),
+        _ => {}
+    }
+
+    let mut config = cli::Config::parse();
+
+    // Resolve and validate configuration
+    if let Err(e) = config.resolve().and_then(|_| config.validate()) {
+        if config.show_config {
+            println!("Configuration error: {}", e);
+            output::exit(1);
+        }
+        output::configure(config.output_config());
+        fail_to_start(
+            config.never_exit,
+            error_not_found!("Configuration error: {}", e),
+        )
+        .await;
+    }
 
-    // Validate configuration
-    if let Err(e) = config.validate() {
-        debug!("error: Configuration error: {}", e);
-        std::process::exit(1);
+    // Everything that contributes to the configuration has been merged at this point
+    if config.show_config {
+        let settings = serde_json::to_string_pretty(&config.settings()).unwrap_or_default();
+        println!("{}", settings);
+        output::exit(0);
     }
 
+    output::configure(config.output_config());
+
     debug!("Starting waybar-dbus-monitor");
-    debug!("Interface: {}", config.interface);
-    debug!("Monitor: {}", config.monitor);
+    if let Some(preset) = config.preset {
+        debug!("Preset: {:?}", preset);
+    }
+    if let Some(name) = &config.name_owner {
+        debug!("Name owner: {}", name);
+    }
+    debug!("Bus: {:?}", config.bus);
+    debug!("Interface: {}", config.interface());
+    if !config.interface_fallback.is_empty() {
+        debug!("Interface fallbacks: {:?}", config.interface_fallback);
+    }
+    debug!("Monitors: {:?}", config.monitors());
+    debug!("Message type: {:?}", config.msg_type);
+    if let Some(path) = &config.path {
+        debug!("Path: {}", path);
+    }
+    if !config.key_path().is_empty() {
+        debug!("Dictionary key path: {:?}", config.key_path());
+    }
+    if let Some(key) = &config.array_struct_key {
+        debug!(
+            "Array struct key: '{}', field {}",
+            key, config.array_struct_field
+        );
+    }
+    if !config.struct_field.is_empty() {
+        debug!("Struct field path: {:?}", config.struct_field);
+    }
+    if config.properties_changed {
+        debug!("Watching PropertiesChanged for the monitored property");
+    }
+    if let Some(seconds) = config.poll_backup {
+        debug!("Polling the status property every {}s as a backup", seconds);
+    }
     debug!("Type handler: {:?}", config.type_handler);
+    debug!("Unexpected values: {:?}", config.on_unexpected);
+    if let Some(signal) = &config.emit_signal {
+        debug!("Emitting values as signal: {}", signal);
+    }
+    for monitor_override in &config.monitor_overrides {
+        debug!(
+            "Type handler for {}: {:?}",
+            monitor_override.member, monitor_override.handler
+        );
+    }
 
     if let Some(status) = &config.status {
         debug!("Status configuration: {}", status);
@@ -40,23 +123,134 @@ async fn main() -> Result<(), AppError> {
         cli::TypeHandler::Boolean {
             return_true,
             return_false,
+            true_value,
         } => {
             debug!("Boolean handler configured:");
             debug!("  Return on true: '{}'", return_true);
             debug!("  Return on false: '{}'", return_false);
+            if let Some(true_value) = true_value {
+                debug!("  String true value: '{}'", true_value);
+            }
+        }
+        cli::TypeHandler::String {
+            empty_string_output,
+            normalize_whitespace,
+            map_file,
+        } => {
+            debug!("String handler configured:");
+            if let Some(placeholder) = empty_string_output {
+                debug!("  Return on empty string: '{}'", placeholder);
+            }
+            debug!("  Normalize whitespace: {}", normalize_whitespace);
+            if let Some(map) = map_file {
+                debug!("  Map file: {:?}", map);
+            }
+        }
+        cli::TypeHandler::Integer {
+            emit_delta,
+            delta_first_absolute,
+            bars,
+            bars_min,
+            bars_max,
+            map,
+            map_file,
+            with_raw,
+            group_digits,
+            unit,
+            percentage_only,
+        } => {
+            debug!("Integer handler configured:");
+            if *emit_delta {
+                debug!(
+                    "  Emitting deltas (first value absolute: {})",
+                    delta_first_absolute
+                );
+            }
+            if let Some(bars) = bars {
+                debug!("  Bars '{}' over range {}..={}", bars, bars_min, bars_max);
+            }
+            if let Some(map) = map {
+                debug!("  Map: {:?}", map);
+            }
+            if let Some(map) = map_file {
+                debug!("  Map file: {:?}", map);
+            }
+            if let Some(position) = with_raw {
+                debug!("  Raw value position: {:?}", position);
+            }
+            if let Some(separator) = group_digits {
+                debug!("  Digit group separator: '{}'", separator);
+            }
+            if let Some(unit) = unit {
+                debug!("  Unit: '{}'", unit);
+            }
+            if *percentage_only {
+                debug!("  Percentage only");
+            }
+        }
+        cli::TypeHandler::Float {
+            precision,
+            float_epsilon,
+        } => {
+            debug!("Float handler configured:");
+            if let Some(precision) = precision {
+                debug!("  Precision: {} digits", precision);
+            }
+            debug!("  Epsilon: {}", float_epsilon);
         }
     }
 
-    let listener = DBusListener::new(config);
+    let recorder = match config.record.as_deref().map(recording::Recorder::create) {
+        Some(Ok(recorder)) => Some(recorder),
+        Some(Err(e)) => fail_to_start(config.never_exit, error_not_found!("{}", e)).await,
+        None => None,
+    };
+    if let Some(record) = &config.record {
+        debug!("Recording received messages to {}", record.display());
+    }
+
+    let warmup_check = config.warmup_check;
+    let mut listener = DBusListener::new(config, recorder);
+
+    if let Some(seconds) = warmup_check {
+        match listener.warmup_check(Duration::from_secs(seconds)).await {
+            Ok(true) => output::exit(0),
+            Ok(false) => output::exit(1),
+            Err(error) => {
+                debug!("error: Warmup check failed: {}", error);
+                error.print_error_code();
+                output::exit(error.code() as i32);
+            }
+        }
+    }
 
     // Run the listener, catching any fatal errors
-    if let Err(error) = listener.listen().await {
+    if let Err(error) = listener.run().await {
         debug!("error: Fatal error: {}", error);
 
-        // Print only the error code for waybar (e.g., "E502")
-        error.print_error_code();
-        std::process::exit(error.code() as i32);
+        // Print only the error code for waybar (e.g., "E502"), unless retrying did already
+        output::emit_error_once(&error);
+        output::exit(error.code() as i32);
+    }
+
+    output::exit(0)
+}
+
+/// Give up on an error that keeps monitoring from starting
+///
+/// Exits with code 1, or with --never-exit emits the error code again with the
+/// reconnection backoff, so the process only stops when it receives a signal.
+async fn fail_to_start(never_exit: bool, error: AppError) -> ! {
+    debug!("error: {}", error);
+    if !never_exit {
+        output::exit(1);
     }
 
-    Ok(())
+    let retry_config = RetryConfig::default();
+    let mut attempt = 0;
+    loop {
+        error.print_error_code();
+        timer::sleep(retry_config.delay_for_attempt(attempt)).await;
+        attempt += 1;
+    }
 }
@@ -0,0 +1,176 @@
+/// Mapping of values onto labels
+///
+/// An integer map is written as comma-separated `MIN..MAX=LABEL` entries with
+/// inclusive bounds, either of which may be left out for an open range, or as
+/// `VALUE=LABEL` entries for a single value:
+///
+/// ```text
+/// ..30=low,31..70=mid,71..=high
+/// ```
+///
+/// Entries are checked in order and the first matching range wins.
+///
+/// Larger maps are read from a file with one entry per line, also for string
+/// values with `STRING=LABEL` entries. Blank lines and lines starting with `#`
+/// are skipped:
+///
+/// ```text
+/// # ISO 3166 country codes
+/// de = Germany
+/// fr = France
+/// ```
+use std::collections::BTreeMap;
+
+/// A single `MIN..MAX=LABEL` entry
+#[derive(Debug, Clone)]
+struct RangeLabel {
+    min: Option<i64>,
+    max: Option<i64>,
+    label: String,
+}
+
+/// Ordered list of value ranges with their labels
+#[derive(Debug, Clone)]
+pub struct RangeMap {
+    ranges: Vec<RangeLabel>,
+}
+
+impl RangeMap {
+    /// Parse a map from its command line form
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let ranges = spec
+            .split(',')
+            .map(|entry| Self::parse_entry(entry.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RangeMap { ranges })
+    }
+
+    /// Load a map from a file with one entry per line
+    pub fn load(path: &str) -> Result<Self, String> {
+        let ranges = parse_file(path, Self::parse_entry)?;
+
+        Ok(RangeMap { ranges })
+    }
+
+    /// Parse a single `MIN..MAX=LABEL` or `VALUE=LABEL` entry
+    fn parse_entry(entry: &str) -> Result<RangeLabel, String> {
+        let (range, label) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid map entry '{}': expected MIN..MAX=LABEL", entry))?;
+        let (min, max) = range.split_once("..").unwrap_or((range, range));
+
+        let parse_bound = |bound: &str| -> Result<Option<i64>, String> {
+            let bound = bound.trim();
+            if bound.is_empty() {
+                return Ok(None);
+            }
+            bound
+                .parse()
+                .map(Some)
+                .map_err(|e| format!("Invalid bound '{}' in range '{}': {}", bound, range, e))
+        };
+        let (min, max) = (parse_bound(min)?, parse_bound(max)?);
+
+        if matches!((min, max), (Some(min), Some(max)) if min > max) {
+            return Err(format!("Empty range '{}': minimum is above maximum", range));
+        }
+
+        Ok(RangeLabel {
+            min,
+            max,
+            label: label.trim().to_string(),
+        })
+    }
+
+    /// Command line form of the map, e.g. for --show-config
+    pub fn spec(&self) -> String {
+        let bound = |bound: Option<i64>| bound.map(|bound| bound.to_string()).unwrap_or_default();
+        self.ranges
+            .iter()
+            .map(|range| match (range.min, range.max) {
+                (Some(min), Some(max)) if min == max => format!("{}={}", min, range.label),
+                (min, max) => format!("{}..{}={}", bound(min), bound(max), range.label),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Label of the first range containing `value`
+    pub fn lookup(&self, value: i64) -> Option<&str> {
+        self.ranges
+            .iter()
+            .find(|range| {
+                range.min.is_none_or(|min| value >= min) && range.max.is_none_or(|max| value <= max)
+            })
+            .map(|range| range.label.as_str())
+    }
+}
+
+/// Labels for exact string values, read from a map file
+#[derive(Debug, Clone)]
+pub struct StringMap {
+    labels: BTreeMap<String, String>,
+}
+
+impl StringMap {
+    /// Load a map from a file with one `STRING=LABEL` entry per line
+    ///
+    /// Like for integer maps, the first entry for a string wins.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut labels = BTreeMap::new();
+        for (key, label) in parse_file(path, Self::parse_entry)? {
+            labels.entry(key).or_insert(label);
+        }
+
+        Ok(StringMap { labels })
+    }
+
+    /// Parse a single `STRING=LABEL` entry
+    fn parse_entry(entry: &str) -> Result<(String, String), String> {
+        let (key, label) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid map entry '{}': expected STRING=LABEL", entry))?;
+
+        Ok((key.trim().to_string(), label.trim().to_string()))
+    }
+
+    /// All strings with their labels
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    /// Label for `value`
+    pub fn lookup(&self, value: &str) -> Option<&str> {
+        self.labels.get(value).map(String::as_str)
+    }
+}
+
+/// Parse the entries of a map file, skipping blank lines and `#` comments
+///
+/// All malformed lines are reported at once, with their line numbers.
+fn parse_file<T>(
+    path: &str,
+    parse_entry: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read map file '{}': {}", path, e))?;
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_entry(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,556 @@
+/// Centralized stdout output for waybar
+///
+/// Every record written to stdout goes through this module, so output policies
+/// such as error throttling are applied in a single place.
+use crate::error::AppError;
+use crate::transform::TransformConfig;
+use clap::ValueEnum;
+use log::debug;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Framing of the records written to stdout
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON object per line (waybar), also known as ndjson
+    #[default]
+    #[value(alias = "ndjson")]
+    Json,
+    /// JSON payload prefixed by its length as a 4-byte big-endian integer
+    Binary,
+}
+
+/// Separator written after each JSON record
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    /// Newline, as waybar expects
+    #[default]
+    Newline,
+    /// NUL byte
+    Nul,
+    /// Any other separator, written as given
+    Custom(String),
+}
+
+impl Delimiter {
+    /// Parse `newline`, `nul` or a custom separator
+    pub fn parse(delimiter: &str) -> Result<Self, String> {
+        match delimiter {
+            "newline" => Ok(Delimiter::Newline),
+            "nul" => Ok(Delimiter::Nul),
+            "" => Err("Delimiter cannot be empty".to_string()),
+            custom => Ok(Delimiter::Custom(custom.to_string())),
+        }
+    }
+
+    /// Name of the delimiter as given on the command line
+    pub fn name(&self) -> &str {
+        match self {
+            Delimiter::Newline => "newline",
+            Delimiter::Nul => "nul",
+            Delimiter::Custom(custom) => custom,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Delimiter::Newline => "\n",
+            Delimiter::Nul => "\0",
+            Delimiter::Custom(custom) => custom,
+        }
+    }
+}
+
+/// Fields of the emitted JSON objects
+const JSON_FIELDS: [&str; 4] = ["text", "tooltip", "class", "percentage"];
+
+/// Renaming of the emitted JSON fields for consumers expecting other keys
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldMap(Vec<(String, String)>);
+
+impl FieldMap {
+    /// Parse a comma-separated list of `field=key` pairs, e.g. `text=label,class=state`
+    pub fn parse(map: &str) -> Result<Self, String> {
+        let mut renames: Vec<(String, String)> = Vec::new();
+
+        for pair in map.split(',') {
+            let Some((field, key)) = pair.split_once('=') else {
+                return Err(format!(
+                    "Invalid field mapping '{}', expected FIELD=KEY",
+                    pair
+                ));
+            };
+            let (field, key) = (field.trim(), key.trim());
+
+            if !JSON_FIELDS.contains(&field) {
+                return Err(format!(
+                    "Unknown field '{}', expected one of: {}",
+                    field,
+                    JSON_FIELDS.join(", ")
+                ));
+            }
+            if key.is_empty() {
+                return Err(format!("Empty key for field '{}'", field));
+            }
+            if renames.iter().any(|(other, _)| other == field) {
+                return Err(format!("Field '{}' mapped more than once", field));
+            }
+            renames.push((field.to_string(), key.to_string()));
+        }
+
+        // Two fields under one key would silently overwrite each other
+        let field_map = FieldMap(renames);
+        for (i, field) in JSON_FIELDS.iter().enumerate() {
+            let key = field_map.key_for(field);
+            if JSON_FIELDS[..i]
+                .iter()
+                .any(|other| field_map.key_for(other) == key)
+            {
+                return Err(format!("Key '{}' used for more than one field", key));
+            }
+        }
+
+        Ok(field_map)
+    }
+
+    /// Command line form of the renaming, e.g. for --show-config
+    pub fn spec(&self) -> String {
+        self.0
+            .iter()
+            .map(|(field, key)| format!("{}={}", field, key))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Key under which a field is emitted
+    fn key_for<'a>(&'a self, field: &'a str) -> &'a str {
+        self.0
+            .iter()
+            .find(|(renamed, _)| renamed == field)
+            .map_or(field, |(_, key)| key)
+    }
+
+    /// Rename the fields of a JSON object
+    fn apply(&self, json: &serde_json::Value) -> serde_json::Value {
+        match json {
+            serde_json::Value::Object(object) if !self.0.is_empty() => object
+                .iter()
+                .map(|(field, value)| (self.key_for(field).to_string(), value.clone()))
+                .collect(),
+            _ => json.clone(),
+        }
+    }
+}
+
+/// Coarse state of an emitted record, used for terminal coloring
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tone {
+    #[default]
+    Neutral,
+    Positive,
+    Negative,
+}
+
+impl Tone {
+    /// ANSI escape sequence selecting the color for this tone
+    fn ansi_color(self) -> Option<&'static str> {
+        match self {
+            Tone::Neutral => None,
+            Tone::Positive => Some("\x1b[32m"),
+            Tone::Negative => Some("\x1b[31m"),
+        }
+    }
+}
+
+/// A formatted value ready to be emitted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub json: serde_json::Value,
+    pub tone: Tone,
+}
+
+impl Record {
+    pub fn new(json: serde_json::Value) -> Self {
+        Self {
+            json,
+            tone: Tone::Neutral,
+        }
+    }
+
+    pub fn with_tone(mut self, tone: Tone) -> Self {
+        self.tone = tone;
+        self
+    }
+}
+
+/// Output settings, configured once at startup
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    /// Minimum interval between two emitted error codes
+    pub error_throttle: Option<Duration>,
+    /// Framing of the emitted records
+    pub format: OutputFormat,
+    /// Separator after each record in JSON format
+    pub delimiter: Delimiter,
+    /// Renamed keys of the emitted JSON fields
+    pub field_map: FieldMap,
+    /// External command applied to the text of every value
+    pub transform: Option<TransformConfig>,
+    /// Maximum number of characters in a value's text
+    pub max_length: Option<usize>,
+    /// Records buffered while stdout is not being read, before older ones are dropped
+    pub buffer: usize,
+    /// Wrap JSON lines in ANSI colors (only enabled when stdout is a terminal)
+    pub color: bool,
+    /// Skip records identical to the previously written one, values and errors alike
+    pub dedup: bool,
+}
+
+static CONFIG: OnceLock<OutputConfig> = OnceLock::new();
+static LAST_ERROR_EMIT: Mutex<Option<Instant>> = Mutex::new(None);
+static LAST_PAYLOAD: Mutex<Option<String>> = Mutex::new(None);
+static LAST_EMITTED_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Set the output configuration (only the first call has an effect)
+pub fn configure(config: OutputConfig) {
+    if CONFIG.set(config).is_err() {
+        debug!("warn: Output configuration already set, ignoring");
+    }
+}
+
+fn config() -> &'static OutputConfig {
+    CONFIG.get_or_init(OutputConfig::default)
+}
+
+/// Write a JSON value as a single record to stdout
+pub fn emit_json(json: &serde_json::Value) -> Result<(), String> {
+    set_last_emitted_error(None);
+    write_record(json, Tone::Neutral)
+}
+
+fn write_record(json: &serde_json::Value, tone: Tone) -> Result<(), String> {
+    let payload = config().field_map.apply(json).to_string();
+
+    if config().dedup {
+        let mut last_payload = LAST_PAYLOAD.lock().unwrap_or_else(|e| e.into_inner());
+        if repeats_last_payload(&mut last_payload, &payload) {
+            debug!("Record unchanged, not emitted");
+            return Ok(());
+        }
+    }
+
+    let bytes = match config().format {
+        OutputFormat::Json => {
+            let delimiter = config().delimiter.as_str();
+            match tone.ansi_color().filter(|_| config().color) {
+                Some(color) => format!("{}{}\x1b[0m{}", color, payload, delimiter),
+                None => format!("{}{}", payload, delimiter),
+            }
+            .into_bytes()
+        }
+        OutputFormat::Binary => {
+            let len = u32::try_from(payload.len())
+                .map_err(|_| format!("Record too large: {} bytes", payload.len()))?;
+            let mut bytes = len.to_be_bytes().to_vec();
+            bytes.extend_from_slice(payload.as_bytes());
+            bytes
+        }
+    };
+
+    enqueue(bytes);
+    Ok(())
+}
+
+/// Check whether `payload` is the same as the last one, and remember it as the last one
+fn repeats_last_payload(last_payload: &mut Option<String>, payload: &str) -> bool {
+    if last_payload.as_deref() == Some(payload) {
+        return true;
+    }
+    *last_payload = Some(payload.to_string());
+    false
+}
+
+/// Records waiting for the writer thread
+struct Queue {
+    records: VecDeque<Vec<u8>>,
+    /// Whether the writer thread is currently writing a record
+    writing: bool,
+}
+
+/// Maximum time to wait for queued records when exiting
+const EXIT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    records: VecDeque::new(),
+    writing: false,
+});
+static QUEUE_CHANGED: Condvar = Condvar::new();
+static WRITER: OnceLock<()> = OnceLock::new();
+
+/// Hand a record to the writer thread
+///
+/// Stdout is written on a separate thread, so a consumer that stops reading
+/// can't stall D-Bus message handling. If the buffer is full, all queued
+/// records are dropped in favor of the latest one, as only the latest state
+/// matters to a status bar.
+fn enqueue(bytes: Vec<u8>) {
+    WRITER.get_or_init(|| {
+        thread::spawn(write_queued_records);
+    });
+
+    let mut queue = lock_queue();
+    if queue.records.len() >= config().buffer.max(1) {
+        debug!(
+            "warn: Output consumer stalled, dropped {} records",
+            queue.records.len()
+        );
+        queue.records.clear();
+    }
+    queue.records.push_back(bytes);
+    QUEUE_CHANGED.notify_all();
+}
+
+fn lock_queue() -> MutexGuard<'static, Queue> {
+    QUEUE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Writer thread: write queued records to stdout in order
+fn write_queued_records() {
+    loop {
+        let bytes = {
+            let mut queue = lock_queue();
+            loop {
+                if let Some(bytes) = queue.records.pop_front() {
+                    queue.writing = true;
+                    break bytes;
+                }
+                queue = QUEUE_CHANGED.wait(queue).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let result = stdout.write_all(&bytes).and_then(|_| stdout.flush());
+        if let Err(e) = result {
+            debug!("error: Failed to write to stdout: {}", e);
+        }
+        drop(stdout);
+
+        lock_queue().writing = false;
+        QUEUE_CHANGED.notify_all();
+    }
+}
+
+/// Wait until all queued records are written to stdout, or the timeout passes
+fn flush(timeout: Duration) {
+    let queue = lock_queue();
+    let (queue, result) = QUEUE_CHANGED
+        .wait_timeout_while(queue, timeout, |queue| {
+            !queue.records.is_empty() || queue.writing
+        })
+        .unwrap_or_else(|e| e.into_inner());
+    if result.timed_out() {
+        debug!(
+            "warn: Output consumer stalled, {} records not written",
+            queue.records.len()
+        );
+    }
+}
+
+/// Write the queued records, then exit the process
+///
+/// All exit paths go through here, so the last record is never lost. A
+/// consumer that stopped reading can delay the exit by at most [`EXIT_FLUSH_TIMEOUT`].
+pub fn exit(code: i32) -> ! {
+    flush(EXIT_FLUSH_TIMEOUT);
+    std::process::exit(code)
+}
+
+/// Write a value record to stdout, applying the value transformations
+pub async fn emit_value(mut record: Record) -> Result<(), String> {
+    let text = record.json.get("text").and_then(|text| text.as_str());
+    if let (Some(transform), Some(text)) = (&config().transform, text) {
+        let transformed = transform.apply(text).await?;
+        record.json["text"] = serde_json::Value::String(transformed);
+    }
+
+    if let Some(max_length) = config().max_length {
+        let text = record.json.get("text").and_then(|text| text.as_str());
+        if let Some(truncated) = text.and_then(|text| truncate(text, max_length)) {
+            record.json["text"] = serde_json::Value::String(truncated);
+        }
+    }
+
+    set_last_emitted_error(None);
+    write_record(&record.json, record.tone)
+}
+
+/// Shorten text to at most `max_length` characters, ending with an ellipsis
+///
+/// Works on characters rather than bytes so multibyte UTF-8 sequences are never
+/// split. Returns `None` if the text already fits.
+fn truncate(text: &str, max_length: usize) -> Option<String> {
+    if text.chars().count() <= max_length {
+        return None;
+    }
+
+    let mut truncated: String = text.chars().take(max_length.saturating_sub(1)).collect();
+    if max_length > 0 {
+        truncated.push('…');
+    }
+    Some(truncated)
+}
+
+/// Write an error code to stdout, unless the error throttle window is still open
+pub fn emit_error(error: &AppError) {
+    if let Some(throttle) = config().error_throttle {
+        let mut last_emit = LAST_ERROR_EMIT.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if last_emit.is_some_and(|last| now.duration_since(last) < throttle) {
+            debug!("Error code {} throttled", error.format_for_waybar());
+            return;
+        }
+        *last_emit = Some(now);
+    }
+
+    set_last_emitted_error(Some(error.to_string()));
+    if let Err(e) = write_record(&error_json(error), Tone::Negative) {
+        debug!("error: {}", e);
+    }
+}
+
+/// Write an error code to stdout, unless it is the last record emitted already
+///
+/// Errors of retried operations are emitted where they occur, so reporting them
+/// again when they end a listen cycle would repeat the code.
+pub fn emit_error_once(error: &AppError) {
+    let repeated = LAST_EMITTED_ERROR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_deref()
+        .is_some_and(|last| last == error.to_string());
+    if repeated {
+        debug!("Error code {} already emitted", error.format_for_waybar());
+    } else {
+        emit_error(error);
+    }
+}
+
+fn set_last_emitted_error(error: Option<String>) {
+    *LAST_EMITTED_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = error;
+}
+
+/// The error code becomes the text, the error message becomes the tooltip
+fn error_json(error: &AppError) -> serde_json::Value {
+    serde_json::json!({
+        "text": error.format_for_waybar(),
+        "tooltip": error.to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn truncate_keeps_text_that_fits() {
+        assert_eq!(truncate("abc", 3), None);
+        assert_eq!(truncate("äöü", 3), None);
+        assert_eq!(truncate("", 0), None);
+    }
+
+    #[test]
+    fn truncate_counts_characters_not_bytes() {
+        assert_eq!(truncate("äöüß", 3).as_deref(), Some("äö…"));
+        assert_eq!(truncate("日本語テキスト", 4).as_deref(), Some("日本語…"));
+        assert_eq!(truncate("🎵🎶🎵🎶", 2).as_deref(), Some("🎵…"));
+    }
+
+    #[test]
+    fn truncate_to_tiny_lengths() {
+        assert_eq!(truncate("äbc", 1).as_deref(), Some("…"));
+        assert_eq!(truncate("äbc", 0).as_deref(), Some(""));
+    }
+
+    /// Whether each record in turn would be written with --dedup
+    fn deduplicated(records: &[serde_json::Value]) -> Vec<bool> {
+        let mut last_payload = None;
+        records
+            .iter()
+            .map(|record| !repeats_last_payload(&mut last_payload, &record.to_string()))
+            .collect()
+    }
+
+    fn value(text: &str) -> serde_json::Value {
+        serde_json::json!({ "text": text })
+    }
+
+    #[test]
+    fn dedup_skips_repeated_values() {
+        let records = [
+            value("on"),
+            value("on"),
+            value("off"),
+            value("off"),
+            value("on"),
+        ];
+        assert_eq!(deduplicated(&records), [true, false, true, false, true]);
+    }
+
+    #[test]
+    fn dedup_alternates_between_errors_and_values() {
+        let error = error_json(&AppError::service_unavailable("gone"));
+        let records = [
+            value("on"),
+            error.clone(),
+            error.clone(),
+            value("on"),
+            value("on"),
+            error.clone(),
+            value("on"),
+        ];
+        assert_eq!(
+            deduplicated(&records),
+            [true, true, false, true, false, true, true]
+        );
+    }
+
+    #[test]
+    fn dedup_compares_whole_records() {
+        let records = [
+            error_json(&AppError::service_unavailable("gone")),
+            error_json(&AppError::service_unavailable("still gone")),
+            serde_json::json!({ "text": "on", "tooltip": "a" }),
+            serde_json::json!({ "text": "on", "tooltip": "b" }),
+        ];
+        assert_eq!(deduplicated(&records), [true; 4]);
+    }
+
+    #[test]
+    fn exit_writes_the_last_record() {
+        if test_support::in_child() {
+            for i in 0..1000 {
+                emit_json(&serde_json::json!({ "text": i.to_string() })).unwrap();
+            }
+            emit_json(&serde_json::json!({ "text": "last" })).unwrap();
+            exit(3);
+        }
+
+        // Exiting ends the process, so run this test again in a child process
+        let output = test_support::child_test("output::tests::exit_writes_the_last_record")
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        // The test harness reports the test name on the line the first record continues
+        let records: Vec<_> = stdout
+            .lines()
+            .filter_map(|line| line.find('{').map(|start| &line[start..]))
+            .collect();
+        assert_eq!(records.last(), Some(&r#"{"text":"last"}"#), "{}", stdout);
+    }
+}
@@ -0,0 +1,179 @@
+/// Presets for well-known D-Bus services
+///
+/// Presets fill in the interface, member, status query and bus for common
+/// monitoring targets, so users don't need to know the D-Bus details.
+use crate::cli::{BusType, Config, TypeHandler};
+use crate::dbus_listener;
+use clap::ValueEnum;
+use zbus::Connection;
+
+const SYSTEMD_SERVICE: &str = "org.freedesktop.systemd1";
+const SYSTEMD_UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const SYSTEMD_UNIT_PATH_PREFIX: &str = "/org/freedesktop/systemd1/unit/";
+const SYSTEMD_MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const SYSTEMD_MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+const DBUS_INTERFACE: &str = "org.freedesktop.DBus";
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+const LOGIND_MANAGER_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIND_SESSION_PATH_PREFIX: &str = "/org/freedesktop/login1/session/";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+/// Named presets for well-known signals
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Whether the system is about to suspend (logind PrepareForSleep)
+    LogindSleep,
+    /// Whether the current session is locked (logind LockedHint)
+    LogindLock,
+}
+
+/// Escape a string into a single D-Bus object path element, like `sd_bus_path_encode`
+///
+/// Every byte that is not an ASCII alphanumeric (and a leading digit) is
+/// replaced by `_` followed by its two-digit lowercase hex value.
+fn escape_path_element(name: &str) -> String {
+    if name.is_empty() {
+        return "_".to_string();
+    }
+
+    let mut escaped = String::with_capacity(name.len());
+    for (i, byte) in name.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() && !(i == 0 && byte.is_ascii_digit()) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+/// Derive the systemd object path for a unit name
+///
+/// Names without a unit type suffix are treated as services, like `systemctl` does.
+pub fn systemd_unit_object_path(unit: &str) -> String {
+    let unit = if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{}.service", unit)
+    };
+    format!("{}{}", SYSTEMD_UNIT_PATH_PREFIX, escape_path_element(&unit))
+}
+
+/// Configure monitoring of a systemd unit's `ActiveState`, mapping `active` to true
+pub fn apply_systemd_unit(config: &mut Config, unit: &str) {
+    let object_path = systemd_unit_object_path(unit);
+
+    config.interface = Some(SYSTEMD_UNIT_INTERFACE.to_string());
+    config.monitor = vec!["ActiveState".to_string()];
+    config.properties_changed = true;
+    config.status = Some(format!(
+        "{}{} {} ActiveState",
+        SYSTEMD_SERVICE, object_path, SYSTEMD_UNIT_INTERFACE
+    ));
+    config.path = Some(object_path);
+
+    // System units are the common case, user units can be selected with --bus session
+    if config.bus == BusType::Auto {
+        config.bus = BusType::System;
+    }
+
+    if let TypeHandler::Boolean { true_value, .. } = &mut config.type_handler {
+        true_value.get_or_insert_with(|| "active".to_string());
+    }
+}
+
+/// Ask systemd to emit unit signals, including the `PropertiesChanged` of --systemd-unit
+///
+/// systemd only emits them while at least one client is subscribed. The
+/// subscription ends when the connection is closed.
+pub async fn subscribe_systemd(connection: &Connection) -> zbus::Result<()> {
+    connection
+        .call_method(
+            Some(SYSTEMD_SERVICE),
+            SYSTEMD_MANAGER_PATH,
+            Some(SYSTEMD_MANAGER_INTERFACE),
+            "Subscribe",
+            &(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Configure one of the named presets
+///
+/// logind only runs on the system bus, so these presets always use it.
+pub fn apply_preset(config: &mut Config, preset: Preset) -> Result<(), String> {
+    match preset {
+        Preset::LogindSleep => {
+            config.interface = Some(LOGIND_MANAGER_INTERFACE.to_string());
+            config.monitor = vec!["PrepareForSleep".to_string()];
+            config.status = Some(format!(
+                "{}{} {} PreparingForSleep",
+                LOGIND_SERVICE, LOGIND_MANAGER_PATH, LOGIND_MANAGER_INTERFACE
+            ));
+            config.path = Some(LOGIND_MANAGER_PATH.to_string());
+        }
+        Preset::LogindLock => {
+            // Signals come from the session's real path, "auto" only works for method
+            // calls, and without a path the changes of all sessions would be watched
+            let session_id = std::env::var("XDG_SESSION_ID")
+                .ok()
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| {
+                    "--preset logind-lock requires XDG_SESSION_ID to find the current session"
+                        .to_string()
+                })?;
+            let session_path = format!(
+                "{}{}",
+                LOGIND_SESSION_PATH_PREFIX,
+                escape_path_element(&session_id)
+            );
+
+            config.interface = Some(LOGIND_SESSION_INTERFACE.to_string());
+            config.monitor = vec!["LockedHint".to_string()];
+            config.properties_changed = true;
+            config.status = Some(format!(
+                "{}{} {} LockedHint",
+                LOGIND_SERVICE, session_path, LOGIND_SESSION_INTERFACE
+            ));
+            config.path = Some(session_path);
+        }
+    }
+
+    config.bus = BusType::System;
+    Ok(())
+}
+
+/// Check that the object a preset monitors exists and implements the preset's interface
+///
+/// Without this, a missing service or session would only show as a value that
+/// never changes.
+pub async fn check_preset(connection: &Connection, config: &Config) -> Result<(), String> {
+    let (Some(path), Some(interface)) = (config.path.as_deref(), config.interface.as_deref())
+    else {
+        return Ok(());
+    };
+
+    let provided = dbus_listener::provides_interface(connection, LOGIND_SERVICE, path, interface)
+        .await
+        .map_err(|e| format!("Failed to reach {} at {}: {}", LOGIND_SERVICE, path, e))?;
+    if !provided {
+        return Err(format!(
+            "{} at {} does not implement {}",
+            LOGIND_SERVICE, path, interface
+        ));
+    }
+    Ok(())
+}
+
+/// Configure monitoring of `NameOwnerChanged` for the --name-owner name
+///
+/// The listener turns these signals into a boolean that is true while the
+/// name has an owner, and queries `NameHasOwner` for the initial value.
+pub fn apply_name_owner(config: &mut Config) {
+    config.interface = Some(DBUS_INTERFACE.to_string());
+    config.monitor = vec!["NameOwnerChanged".to_string()];
+}
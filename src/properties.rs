@@ -0,0 +1,109 @@
+/// Listing of an object's current property values
+///
+/// `waybar-dbus-monitor properties service/path interface` prints every
+/// property of the interface with its type and value, to help find the right
+/// `--status` argument.
+use crate::cli::{self, BusType};
+use crate::dbus_listener::{self, PROPERTIES_INTERFACE};
+use crate::error::AppError;
+use clap::Parser;
+use std::collections::BTreeMap;
+use zbus::Proxy;
+use zbus::zvariant::OwnedValue;
+
+/// List the property values of an object, to help write --status
+#[derive(Parser, Debug)]
+#[command(
+    name = "waybar-dbus-monitor properties",
+    bin_name = "waybar-dbus-monitor properties"
+)]
+struct PropertiesArgs {
+    /// Object in format "service/path"
+    service_path: String,
+
+    /// Interface whose properties are listed
+    interface: String,
+
+    /// Message bus to connect to
+    #[arg(long, env = "WDM_BUS", value_enum, default_value_t = BusType::Auto)]
+    bus: BusType,
+}
+
+/// List the properties and return the process exit code
+pub async fn run() -> i32 {
+    // The first argument is the subcommand name, which clap sees as the binary name
+    let args = PropertiesArgs::parse_from(std::env::args().skip(1));
+
+    match list(&args).await {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+async fn list(args: &PropertiesArgs) -> Result<(), String> {
+    let (service, object_path) = cli::parse_service_path(&args.service_path)?;
+    let connection = dbus_listener::establish_connection(args.bus)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let proxy = Proxy::new(
+        &connection,
+        service.as_str(),
+        object_path.as_str(),
+        PROPERTIES_INTERFACE,
+    )
+    .await
+    .map_err(|e| format!("Invalid object '{}': {}", args.service_path, e))?;
+
+    let properties: BTreeMap<String, OwnedValue> = proxy
+        .call("GetAll", &(args.interface.as_str(),))
+        .await
+        .map_err(|e| describe_error(args, e))?;
+
+    println!("{} {}", args.service_path, args.interface);
+    if properties.is_empty() {
+        println!("  (no properties)");
+        return Ok(());
+    }
+    for (name, value) in &properties {
+        println!("  {} ({}): {}", name, value.value_signature(), **value);
+    }
+
+    // Any of the listed names completes a --status argument
+    let example = properties.keys().next().map_or("", String::as_str);
+    println!(
+        "Example: --status \"{} {} {}\"",
+        args.service_path, args.interface, example
+    );
+    Ok(())
+}
+
+/// Explain why GetAll failed, naming objects that don't implement the Properties interface
+///
+/// Services report a missing Properties interface either as an unknown method
+/// or as an unknown interface, the latter also for an unknown `interface` argument.
+fn describe_error(args: &PropertiesArgs, error: zbus::Error) -> String {
+    let zbus::Error::MethodError(name, message, _) = &error else {
+        return AppError::from(error).to_string();
+    };
+    let message = message.as_deref().unwrap_or_default();
+
+    if name.ends_with(".UnknownMethod")
+        || (name.ends_with(".UnknownInterface") && message.contains(PROPERTIES_INTERFACE))
+    {
+        format!(
+            "{} does not implement {}: {}",
+            args.service_path, PROPERTIES_INTERFACE, message
+        )
+    } else if name.ends_with(".UnknownInterface") {
+        format!(
+            "{} has no interface {}: {}",
+            args.service_path, args.interface, message
+        )
+    } else {
+        AppError::from(error).to_string()
+    }
+}
@@ -0,0 +1,190 @@
+/// Recording of received D-Bus messages for later replay
+///
+/// A recording starts with the magic line `WDMREC1\n`, followed by one frame
+/// per received message. All integers are big-endian:
+///
+/// ```text
+/// u64  milliseconds since the recording started
+/// u32  length, then the UTF-8 member name (empty if the message has none)
+/// u32  length, then the body signature
+/// u8   endianness of the body, 'l' (little) or 'B' (big)
+/// u32  length, then the body bytes in D-Bus wire format
+/// ```
+///
+/// The body is stored exactly as received, so it deserializes with the
+/// recorded signature and endianness into the same values the handlers saw.
+/// There is no replay yet; the decoder in the tests documents how to read
+/// the format back.
+use log::debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Magic line at the start of every recording
+const MAGIC: &[u8] = b"WDMREC1\n";
+
+/// Writer appending received messages to a recording file
+pub struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Create the recording file, replacing any existing one
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create recording '{}': {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(MAGIC)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("Failed to write recording '{}': {}", path.display(), e))?;
+
+        Ok(Recorder {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append a message as a single frame
+    ///
+    /// Each frame is flushed right away, so a recording is usable even if the
+    /// process is killed.
+    pub fn record(&mut self, message: &zbus::Message) -> std::io::Result<()> {
+        let header = message.header();
+        let member = header.member().map(|m| m.as_str()).unwrap_or_default();
+        let body = message.body();
+        let signature = body.signature().to_string();
+        let elapsed = self.started.elapsed().as_millis() as u64;
+
+        self.writer.write_all(&elapsed.to_be_bytes())?;
+        self.write_field(member.as_bytes())?;
+        self.write_field(signature.as_bytes())?;
+        self.writer
+            .write_all(&[message.primary_header().endian_sig() as u8])?;
+        self.write_field(body.data())?;
+        self.writer.flush()
+    }
+
+    /// Write a length-prefixed field
+    fn write_field(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        let length = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Field too large to record",
+            )
+        })?;
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(bytes)
+    }
+}
+
+/// Record a message if recording is enabled, disabling it after the first failure
+///
+/// Recording is a debugging aid, so write errors never affect the live output.
+pub fn record(recorder: &mut Option<Recorder>, message: &zbus::Message) {
+    if let Some(Err(e)) = recorder.as_mut().map(|r| r.record(message)) {
+        debug!("warn: Failed to write recording, recording stopped: {}", e);
+        *recorder = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::serialized::{Context, Data};
+    use zbus::zvariant::{Endian, OwnedValue, Structure, Value};
+
+    /// A frame read back from a recording
+    struct Frame {
+        member: String,
+        signature: String,
+        endian: u8,
+        body: Vec<u8>,
+    }
+
+    /// Decoder for the recording format, the counterpart of [`Recorder`]
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn take(&mut self, length: usize) -> &[u8] {
+            let (taken, rest) = self.0.split_at(length);
+            self.0 = rest;
+            taken
+        }
+
+        fn u32(&mut self) -> usize {
+            u32::from_be_bytes(self.take(4).try_into().unwrap()) as usize
+        }
+
+        fn field(&mut self) -> Vec<u8> {
+            let length = self.u32();
+            self.take(length).to_vec()
+        }
+
+        fn frames(mut self) -> Vec<Frame> {
+            assert_eq!(self.take(MAGIC.len()), MAGIC);
+            let mut frames = Vec::new();
+            while !self.0.is_empty() {
+                self.take(8);
+                frames.push(Frame {
+                    member: String::from_utf8(self.field()).unwrap(),
+                    signature: String::from_utf8(self.field()).unwrap(),
+                    endian: self.take(1)[0],
+                    body: self.field(),
+                });
+            }
+            frames
+        }
+    }
+
+    impl Frame {
+        /// Deserialize the body like the listener does, as a struct of all arguments
+        fn arguments(&self) -> OwnedValue {
+            let endian = match self.endian {
+                b'l' => Endian::Little,
+                b'B' => Endian::Big,
+                other => panic!("invalid endianness {}", other),
+            };
+            let data = Data::new(self.body.clone(), Context::new_dbus(endian, 0));
+            let (arguments, _): (Structure, _) = data
+                .deserialize_for_dynamic_signature(self.signature.as_str())
+                .unwrap();
+            Value::from(arguments).try_into().unwrap()
+        }
+    }
+
+    fn message(member: &str, body: &(bool, i32, &str)) -> zbus::Message {
+        zbus::Message::signal("/", "a.b", member)
+            .unwrap()
+            .build(body)
+            .unwrap()
+    }
+
+    #[test]
+    fn recorded_bodies_deserialize_to_the_same_values() {
+        let path = std::env::temp_dir().join(format!("wdm-recording-{}", std::process::id()));
+        let messages = [
+            message("First", &(true, -5, "ä")),
+            message("Second", &(false, 7, "")),
+        ];
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        for message in &messages {
+            recorder.record(message).unwrap();
+        }
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let frames = Reader(&bytes).frames();
+        assert_eq!(frames.len(), messages.len());
+        for (frame, message) in frames.iter().zip(&messages) {
+            let body = message.body();
+            let expected = Value::from(body.deserialize::<Structure>().unwrap());
+            assert_eq!(frame.member, message.header().member().unwrap().as_str());
+            assert_eq!(frame.signature, body.signature().to_string());
+            assert_eq!(frame.arguments(), OwnedValue::try_from(expected).unwrap());
+        }
+    }
+}
@@ -3,10 +3,10 @@
 /// This module provides retry functionality for operations that may fail
 /// temporarily, such as D-Bus connections or interface availability.
 use crate::error::AppError;
+use crate::timer;
 use log::debug;
 use std::future::Future;
 use std::time::Duration;
-use tokio::time::sleep;
 
 /// Configuration for retry behavior
 #[derive(Debug, Clone)]
@@ -30,7 +30,11 @@ impl Default for RetryConfig {
 
 impl RetryConfig {
     /// Calculate delay for a given attempt (0-based)
-    fn delay_for_attempt(&self, attempt: usize) -> Duration {
+    ///
+    /// The delay never drops below `initial_delay_ms`, even with a backoff factor
+    /// below 1 or a `max_delay_ms` smaller than the initial delay, so callers
+    /// looping over failures can't spin on a bus that refuses connections instantly.
+    pub fn delay_for_attempt(&self, attempt: usize) -> Duration {
         if attempt == 0 {
             return Duration::from_millis(self.initial_delay_ms);
         }
@@ -38,7 +42,7 @@ impl RetryConfig {
         let delay_ms = (self.initial_delay_ms as f64 * self.backoff_factor.powi(attempt as i32))
             .min(self.max_delay_ms as f64) as u64;
 
-        Duration::from_millis(delay_ms)
+        Duration::from_millis(delay_ms.max(self.initial_delay_ms))
     }
 }
 
@@ -65,7 +69,7 @@ where
                 config.max_attempts,
                 delay
             );
-            sleep(delay).await;
+            timer::sleep(delay).await;
         } else {
             debug!(
                 "Attempting {} (attempt 1/{})",
@@ -131,3 +135,50 @@ where
 {
     retry_operation_with_config(operation, operation_name, RetryConfig::default()).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assert that no delay is shorter than the initial delay, for many attempts
+    fn assert_never_below_initial_delay(config: &RetryConfig) {
+        let initial = Duration::from_millis(config.initial_delay_ms);
+        for attempt in 0..100 {
+            let delay = config.delay_for_attempt(attempt);
+            assert!(
+                delay >= initial,
+                "attempt {}: {:?} < {:?}",
+                attempt,
+                delay,
+                initial
+            );
+        }
+    }
+
+    #[test]
+    fn default_delays_back_off_up_to_the_maximum() {
+        let config = RetryConfig::default();
+        assert_eq!(config.delay_for_attempt(0), Duration::from_millis(500));
+        assert_eq!(config.delay_for_attempt(1), Duration::from_millis(750));
+        assert_eq!(config.delay_for_attempt(100), Duration::from_millis(5000));
+        assert_never_below_initial_delay(&config);
+    }
+
+    #[test]
+    fn shrinking_backoff_keeps_the_initial_delay() {
+        let config = RetryConfig {
+            backoff_factor: 0.5,
+            ..RetryConfig::default()
+        };
+        assert_never_below_initial_delay(&config);
+    }
+
+    #[test]
+    fn maximum_below_initial_delay_keeps_the_initial_delay() {
+        let config = RetryConfig {
+            max_delay_ms: 10,
+            ..RetryConfig::default()
+        };
+        assert_never_below_initial_delay(&config);
+    }
+}
@@ -0,0 +1,332 @@
+/// Value selection within D-Bus messages
+///
+/// Selectors narrow a message down to the value that the type handler
+/// formats, e.g. a single entry of an `a{sv}` dictionary argument or a field
+/// of one struct in an `a(ss)` array.
+use zbus::zvariant::{OwnedValue, Signature, Structure, Value};
+
+/// Deserialize the arguments of a D-Bus message body as the fields of a struct
+///
+/// The body's signature decides how it is read. A lone bare argument, e.g. body
+/// "b", becomes the only field. zbus reads a struct signature like an argument
+/// list, so a lone struct argument "(b)" yields the same bool as "b", and a lone
+/// "(bi)" yields its fields as if they were the arguments "bi".
+fn arguments(body: &zbus::message::Body) -> Result<Structure<'_>, String> {
+    let signature = match body.signature() {
+        Signature::Unit => return Err("Message has no arguments".to_string()),
+        Signature::Structure(_) => body.signature().clone(),
+        bare => Signature::structure([bare.clone()]),
+    };
+
+    body.data()
+        .deserialize_for_dynamic_signature(&signature)
+        .map(|(arguments, _)| arguments)
+        .map_err(|e| format!("Failed to deserialize message: {}", e))
+}
+
+/// Deserialize all arguments of a D-Bus message body as one struct
+///
+/// The first field is the first argument, so a path of struct fields selects the
+/// argument before descending into it. For a lone struct argument, it selects a
+/// field of that struct, as zbus reads such a body like an argument list.
+pub fn all_arguments(message: &zbus::Message) -> Result<OwnedValue, String> {
+    let body = message.body();
+
+    Value::Structure(arguments(&body)?)
+        .try_into()
+        .map_err(|e| format!("Failed to read message arguments: {}", e))
+}
+
+/// Deserialize the first argument of a D-Bus message body
+pub fn first_argument(message: &zbus::Message) -> Result<OwnedValue, String> {
+    argument(message, 0)?.ok_or_else(|| "Message has no arguments".to_string())
+}
+
+/// Deserialize the argument at `index` of a D-Bus message body, if there is one
+pub fn argument(message: &zbus::Message, index: usize) -> Result<Option<OwnedValue>, String> {
+    let body = message.body();
+
+    arguments(&body)?
+        .into_fields()
+        .into_iter()
+        .nth(index)
+        .map(|argument| {
+            argument
+                .try_into()
+                .map_err(|e| format!("Failed to read message argument: {}", e))
+        })
+        .transpose()
+}
+
+/// Strip all variant wrappers around a value
+pub fn unwrap_variant<'a, 'v>(value: &'a Value<'v>) -> &'a Value<'v> {
+    match value {
+        Value::Value(inner) => unwrap_variant(inner),
+        _ => value,
+    }
+}
+
+/// Follow a path of string keys through nested dictionaries
+///
+/// Returns `Ok(None)` if a key is missing, and an error if a value on the path
+/// is not a dictionary. The selected value is returned without variant wrappers,
+/// so it can be passed straight to the type handler's extractors.
+pub fn select_keys<'a, 'v>(
+    value: &'a Value<'v>,
+    keys: &[String],
+) -> Result<Option<&'a Value<'v>>, String> {
+    let mut current = unwrap_variant(value);
+
+    for key in keys {
+        let Value::Dict(dict) = current else {
+            return Err(format!(
+                "Cannot select key '{}' from non-dictionary value: {:?}",
+                key, current
+            ));
+        };
+
+        let entry = dict.iter().find(|(k, _)| match unwrap_variant(k) {
+            Value::Str(s) => s.as_str() == key,
+            Value::ObjectPath(p) => p.as_str() == key,
+            _ => false,
+        });
+
+        match entry {
+            Some((_, v)) => current = unwrap_variant(v),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(current))
+}
+
+/// Select a field of the struct whose first field is `key` from an array of structs
+///
+/// Returns `Ok(None)` if no struct matches, and an error if the value is not an
+/// array of structs or the matching struct has no field at `field`. Like
+/// [`select_keys`], the selected value is returned without variant wrappers.
+pub fn select_array_struct<'a, 'v>(
+    value: &'a Value<'v>,
+    key: &str,
+    field: usize,
+) -> Result<Option<&'a Value<'v>>, String> {
+    let Value::Array(array) = unwrap_variant(value) else {
+        return Err(format!(
+            "Cannot select struct '{}' from non-array value: {:?}",
+            key, value
+        ));
+    };
+
+    for element in array.iter() {
+        let Value::Structure(structure) = unwrap_variant(element) else {
+            return Err(format!("Array element is not a struct: {:?}", element));
+        };
+
+        let fields = structure.fields();
+        let matches = match fields.first().map(unwrap_variant) {
+            Some(Value::Str(s)) => s.as_str() == key,
+            Some(Value::ObjectPath(p)) => p.as_str() == key,
+            _ => false,
+        };
+        if matches {
+            return fields
+                .get(field)
+                .map(|value| Some(unwrap_variant(value)))
+                .ok_or_else(|| format!("Struct '{}' has no field {}", key, field));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Follow a path of field indexes through nested structs
+///
+/// The first index selects a field of `value`, each further index a field of
+/// the struct selected before. Errors name the nesting level, counting from 0,
+/// at which the path can't be followed. Like [`select_keys`], the selected value
+/// is returned without variant wrappers.
+pub fn select_struct_fields<'a, 'v>(
+    value: &'a Value<'v>,
+    fields: &[usize],
+) -> Result<&'a Value<'v>, String> {
+    let mut current = unwrap_variant(value);
+
+    for (level, &field) in fields.iter().enumerate() {
+        let Value::Structure(structure) = current else {
+            return Err(format!(
+                "Cannot select field {} at nesting level {} from non-struct value: {:?}",
+                field, level, current
+            ));
+        };
+
+        let struct_fields = structure.fields();
+        current = struct_fields
+            .get(field)
+            .map(unwrap_variant)
+            .ok_or_else(|| {
+                format!(
+                    "Field {} at nesting level {} is out of range, the struct has {} fields",
+                    field,
+                    level,
+                    struct_fields.len()
+                )
+            })?;
+    }
+
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// An `a{sv}` dictionary, with every value wrapped in a variant
+    fn dict<'a>(entries: Vec<(&'a str, Value<'a>)>) -> Value<'a> {
+        Value::from(entries.into_iter().collect::<HashMap<_, _>>())
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn dict_values_are_wrapped_in_variants() {
+        let Value::Dict(dict) = dict(vec![("Active", Value::from(true))]) else {
+            panic!("not a dictionary");
+        };
+        let (_, value) = dict.iter().next().unwrap();
+        assert!(matches!(value, Value::Value(_)), "{:?}", value);
+    }
+
+    #[test]
+    fn select_keys_unwraps_booleans() {
+        let value = dict(vec![
+            ("Active", Value::from(true)),
+            ("Level", Value::from(3)),
+        ]);
+        let selected = select_keys(&value, &keys(&["Active"])).unwrap();
+        assert_eq!(selected, Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn select_keys_unwraps_integers() {
+        let value = dict(vec![
+            ("Active", Value::from(true)),
+            ("Level", Value::from(42i32)),
+        ]);
+        let selected = select_keys(&value, &keys(&["Level"])).unwrap();
+        assert_eq!(selected, Some(&Value::I32(42)));
+    }
+
+    #[test]
+    fn select_keys_unwraps_variant_wrapped_dictionaries() {
+        let value = Value::Value(Box::new(dict(vec![("Level", Value::from(7u8))])));
+        let selected = select_keys(&value, &keys(&["Level"])).unwrap();
+        assert_eq!(selected, Some(&Value::U8(7)));
+    }
+
+    #[test]
+    fn select_keys_follows_nested_dictionaries() {
+        let inner = dict(vec![("Percentage", Value::from(80u32))]);
+        let value = dict(vec![("Battery", inner)]);
+        let selected = select_keys(&value, &keys(&["Battery", "Percentage"])).unwrap();
+        assert_eq!(selected, Some(&Value::U32(80)));
+    }
+
+    #[test]
+    fn select_keys_reports_missing_keys_as_none() {
+        let value = dict(vec![("Active", Value::from(true))]);
+        assert_eq!(select_keys(&value, &keys(&["Missing"])).unwrap(), None);
+    }
+
+    #[test]
+    fn select_keys_rejects_non_dictionaries() {
+        let value = dict(vec![("Active", Value::from(true))]);
+        let error = select_keys(&value, &keys(&["Active", "Nested"])).unwrap_err();
+        assert!(error.contains("non-dictionary"), "{}", error);
+    }
+
+    fn signal() -> zbus::message::Builder<'static> {
+        zbus::Message::signal("/", "a.b", "X").unwrap()
+    }
+
+    #[test]
+    fn first_argument_of_a_bare_body() {
+        let message = signal().build(&(true,)).unwrap();
+        assert_eq!(message.body().signature().to_string(), "b");
+        assert_eq!(*first_argument(&message).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn first_argument_of_a_one_field_struct_body() {
+        let message = signal().build(&((true,),)).unwrap();
+        assert_eq!(message.body().signature().to_string(), "(b)");
+        assert_eq!(*first_argument(&message).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn first_argument_of_several_arguments() {
+        let message = signal().build(&(7u32, "x")).unwrap();
+        assert_eq!(*first_argument(&message).unwrap(), Value::U32(7));
+        assert_eq!(*argument(&message, 1).unwrap().unwrap(), Value::from("x"));
+        assert_eq!(argument(&message, 2).unwrap(), None);
+    }
+
+    #[test]
+    fn first_argument_of_an_empty_body() {
+        let message = signal().build(&()).unwrap();
+        assert!(first_argument(&message).is_err());
+    }
+
+    #[test]
+    fn struct_fields_of_a_nested_struct_argument() {
+        let message = signal().build(&(((true, 5i32), "x"),)).unwrap();
+        assert_eq!(message.body().signature().to_string(), "((bi)s)");
+
+        let arguments = all_arguments(&message).unwrap();
+        let selected = select_struct_fields(&arguments, &[0, 0]).unwrap();
+        assert_eq!(selected, &Value::Bool(true));
+        let selected = select_struct_fields(&arguments, &[0, 1]).unwrap();
+        assert_eq!(selected, &Value::I32(5));
+        let selected = select_struct_fields(&arguments, &[1]).unwrap();
+        assert_eq!(selected, &Value::from("x"));
+    }
+
+    #[test]
+    fn struct_fields_of_a_lone_struct_argument() {
+        let message = signal().build(&((true, 5i32),)).unwrap();
+        assert_eq!(message.body().signature().to_string(), "(bi)");
+
+        let arguments = all_arguments(&message).unwrap();
+        let selected = select_struct_fields(&arguments, &[0]).unwrap();
+        assert_eq!(selected, &Value::Bool(true));
+        let selected = select_struct_fields(&arguments, &[1]).unwrap();
+        assert_eq!(selected, &Value::I32(5));
+    }
+
+    #[test]
+    fn struct_fields_name_the_nesting_level_that_fails() {
+        let message = signal().build(&(((true, 5i32), "x"),)).unwrap();
+        let arguments = all_arguments(&message).unwrap();
+
+        let error = select_struct_fields(&arguments, &[0, 2]).unwrap_err();
+        assert!(
+            error.contains("Field 2 at nesting level 1 is out of range"),
+            "{}",
+            error
+        );
+        let error = select_struct_fields(&arguments, &[2]).unwrap_err();
+        assert!(
+            error.contains("Field 2 at nesting level 0 is out of range"),
+            "{}",
+            error
+        );
+        let error = select_struct_fields(&arguments, &[0, 0, 0]).unwrap_err();
+        assert!(
+            error.contains("at nesting level 2 from non-struct"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,179 @@
+/// End-to-end self-test of the binary
+///
+/// `waybar-dbus-monitor selftest` starts a private message bus, runs this
+/// binary as a monitor for a test signal on it, emits known boolean values and
+/// checks the JSON output. Without `dbus-daemon`, the session bus is used.
+use crate::timer;
+use log::debug;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::Connection;
+
+const INTERFACE: &str = "org.waybar_dbus_monitor.SelfTest";
+const MEMBER: &str = "Toggled";
+
+/// Time to wait for each expected output
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval for re-emitting the test signal until the monitor has subscribed
+const EMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Child process that is killed when dropped
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Run the self-test and return the process exit code
+pub async fn run() -> i32 {
+    match check().await {
+        Ok(()) => {
+            println!("PASS");
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            println!("FAIL");
+            1
+        }
+    }
+}
+
+async fn check() -> Result<(), String> {
+    let bus = start_private_bus();
+    let connection = match &bus {
+        Some((_, address)) => {
+            println!("Using private bus {}", address);
+            zbus::connection::Builder::address(address.as_str())
+                .map_err(|e| format!("Invalid private bus address: {}", e))?
+                .build()
+                .await
+        }
+        None => {
+            println!("Using session bus");
+            Connection::session().await
+        }
+    }
+    .map_err(|e| format!("Failed to connect to the bus: {}", e))?;
+
+    let executable =
+        std::env::current_exe().map_err(|e| format!("Failed to locate the binary: {}", e))?;
+    let mut command = Command::new(executable);
+    command
+        .args([
+            "--bus",
+            "session",
+            "--interface",
+            INTERFACE,
+            "--monitor",
+            MEMBER,
+        ])
+        .args(["boolean", "--return-true", "on", "--return-false", "off"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // The monitor under test must not pick up the options of the user's environment
+    let wdm_variables =
+        std::env::vars_os().filter(|(key, _)| key.to_string_lossy().starts_with("WDM_"));
+    for (key, _) in wdm_variables {
+        command.env_remove(key);
+    }
+    if let Some((_, address)) = &bus {
+        command.env("DBUS_SESSION_BUS_ADDRESS", address);
+    }
+    let mut monitor = ChildGuard(
+        command
+            .spawn()
+            .map_err(|e| format!("Failed to start the monitor: {}", e))?,
+    );
+
+    let stdout = monitor
+        .0
+        .stdout
+        .take()
+        .ok_or_else(|| "Monitor has no stdout".to_string())?;
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    for (value, expected) in [(true, "on"), (false, "off")] {
+        expect_output(&connection, &lines, value, expected).await?;
+        println!("  {} -> {}: ok", value, expected);
+    }
+
+    Ok(())
+}
+
+/// Start a private `dbus-daemon`, returning it with its address
+fn start_private_bus() -> Option<(ChildGuard, String)> {
+    let daemon = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--nopidfile", "--print-address"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut daemon = match daemon {
+        Ok(daemon) => ChildGuard(daemon),
+        Err(e) => {
+            debug!("warn: Could not start dbus-daemon: {}", e);
+            return None;
+        }
+    };
+
+    let mut address = String::new();
+    let stdout = daemon.0.stdout.take()?;
+    BufReader::new(stdout).read_line(&mut address).ok()?;
+    let address = address.trim().to_string();
+
+    (!address.is_empty()).then_some((daemon, address))
+}
+
+/// Emit `value` until the monitor outputs `expected`
+///
+/// The signal is repeated because the monitor may not have subscribed yet.
+async fn expect_output(
+    connection: &Connection,
+    lines: &Receiver<String>,
+    value: bool,
+    expected: &str,
+) -> Result<(), String> {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while Instant::now() < deadline {
+        connection
+            .emit_signal(
+                None::<zbus::names::BusName<'_>>,
+                "/",
+                INTERFACE,
+                MEMBER,
+                &value,
+            )
+            .await
+            .map_err(|e| format!("Failed to emit the test signal: {}", e))?;
+        timer::sleep(EMIT_INTERVAL).await;
+
+        while let Ok(line) = lines.try_recv() {
+            debug!("Monitor output: {}", line);
+            let json: serde_json::Value = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid monitor output '{}': {}", line, e))?;
+            if json["text"] == expected {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(format!(
+        "No '{}' output within {:?} after emitting {}",
+        expected, TIMEOUT, value
+    ))
+}
@@ -0,0 +1,35 @@
+/// Helpers shared by the unit tests
+///
+/// Test configurations start from the same minimal command line, and tests
+/// that change the environment or end the process run again in a child
+/// process, so they can't affect the other tests.
+use std::process::Command;
+
+/// Command line that test configurations start with, before their own options
+const ARGV: [&str; 5] = [
+    "waybar-dbus-monitor",
+    "--interface",
+    "a.b",
+    "--monitor",
+    "X",
+];
+
+/// Set in the child processes started by [`child_test`]
+const CHILD: &str = "WAYBAR_DBUS_MONITOR_TEST_CHILD";
+
+/// The minimal test command line followed by `args`
+pub fn argv<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    ARGV.iter().chain(args).copied().collect()
+}
+
+/// Whether this process is a child started by [`child_test`]
+pub fn in_child() -> bool {
+    std::env::var_os(CHILD).is_some()
+}
+
+/// Command that runs only the test at `path` (e.g. "output::tests::name") in a child process
+pub fn child_test(path: &str) -> Command {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command.args([path, "--exact"]).env(CHILD, "1");
+    command
+}
@@ -0,0 +1,60 @@
+/// Timer primitives used by the listener and the retry logic
+///
+/// All waiting goes through this module, so the timers are defined in a single
+/// place. The binary is built on tokio: besides these timers, `main`, the
+/// `select!` in the listen loop and the transform command (`tokio::process`)
+/// rely on it.
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Wait for the given duration
+pub async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+/// Wait until `deadline`, forever without a deadline
+pub async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Run a future for at most `duration`, returning `None` if it didn't finish in time
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(duration, future).await.ok()
+}
+
+/// Run a future until `deadline`, returning `None` if it didn't finish in time
+pub async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Option<F::Output> {
+    tokio::time::timeout_at(deadline.into(), future).await.ok()
+}
+
+/// Optional periodic timer; a disabled ticker never fires
+pub struct Ticker(Option<tokio::time::Interval>);
+
+impl Ticker {
+    /// Fire immediately and then every `period`, or never without a period
+    pub fn every(period: Option<Duration>) -> Self {
+        Ticker(period.map(tokio::time::interval))
+    }
+
+    /// Fire every `period`, starting one period from now, or never without a period
+    pub fn every_after(period: Option<Duration>) -> Self {
+        Ticker(
+            period.map(|period| {
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+            }),
+        )
+    }
+
+    /// Wait for the next tick, forever if the ticker is disabled
+    pub async fn tick(&mut self) {
+        match &mut self.0 {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
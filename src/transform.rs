@@ -0,0 +1,99 @@
+/// External command transformation of output values
+///
+/// Each value is piped to a freshly spawned `sh -c <command>` and replaced by
+/// the command's stdout. Spawning per value keeps commands independent of
+/// each other, at the cost of a process start for every emitted value.
+use crate::timer;
+use log::debug;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Configuration for the external transform command
+#[derive(Debug, Clone)]
+pub struct TransformConfig {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+impl TransformConfig {
+    /// Run the command with `input` on stdin and return its stdout without trailing newlines
+    ///
+    /// Commands that don't finish within the timeout, including writing their
+    /// input, are killed. The command runs without blocking the runtime.
+    pub async fn apply(&self, input: &str) -> Result<String, String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to spawn transform command: {}", e))?;
+
+        // Write the value and close stdin so the command sees EOF, while reading
+        // stdout at the same time so neither pipe can fill up
+        let stdin = child.stdin.take();
+        let write_input = async move {
+            let Some(mut stdin) = stdin else {
+                return;
+            };
+            if let Err(e) = stdin.write_all(input.as_bytes()).await {
+                // The command may legitimately exit without reading its input
+                debug!("warn: Failed to write to transform command: {}", e);
+            }
+        };
+        let run = async {
+            let (_, output) = tokio::join!(write_input, child.wait_with_output());
+            output
+        };
+
+        // Dropping the unfinished command on timeout kills it
+        let output = timer::timeout(self.timeout, run)
+            .await
+            .ok_or_else(|| format!("Transform command timed out after {:?}", self.timeout))?
+            .map_err(|e| format!("Failed to wait for transform command: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!("Transform command failed with {}", output.status));
+        }
+
+        let output = String::from_utf8(output.stdout)
+            .map_err(|e| format!("Failed to read transform command output: {}", e))?;
+        Ok(output.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(command: &str, timeout_ms: u64) -> TransformConfig {
+        TransformConfig {
+            command: command.to_string(),
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_the_value_by_the_command_output() {
+        let output = transform("tr a-z A-Z", 5000).apply("abc").await;
+        assert_eq!(output.unwrap(), "ABC");
+    }
+
+    #[tokio::test]
+    async fn kills_hung_commands() {
+        let output = transform("sleep 10", 100).apply("abc").await;
+        assert!(output.unwrap_err().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn times_out_writing_to_a_command_that_does_not_read() {
+        // More than a pipe buffer, so writing blocks until the command exits
+        let input = "a".repeat(1 << 20);
+        let output = transform("sleep 10", 100).apply(&input).await;
+        assert!(output.unwrap_err().contains("timed out"));
+    }
+}